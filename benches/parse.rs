@@ -0,0 +1,118 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use fastq2comp::BaseComp;
+use fastq2comp::extract_comp::{run, FASTQReader, SampleArgs};
+
+use regex::Regex;
+use std::io::{BufRead, Cursor};
+
+const READS: usize = 10_000;
+const READ_LEN: usize = 100;
+const TRIM: usize = 50;
+
+/// Generates `READS` FASTQ records of length `READ_LEN`, with pseudo-random bases.
+fn fastq() -> Vec<u8> {
+    let mut state: u32 = 0x2545_f491;
+    let mut data = Vec::with_capacity(READS * (2 * READ_LEN + 16));
+
+    for i in 0..READS {
+        data.extend_from_slice(format!("@read{}\n", i).as_bytes());
+        for _ in 0..READ_LEN {
+            // xorshift32
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            data.push(b"ACGT"[(state % 4) as usize]);
+        }
+        data.extend_from_slice(b"\n+\n");
+        data.extend_from_slice(&[b'I'; READ_LEN]);
+        data.push(b'\n');
+    }
+
+    data
+}
+
+/// Line-based parser as used before the byte-oriented one:
+/// every line is UTF-8 validated and allocated, and every accepted read copied into a new String.
+fn legacy_parse(mut reader: impl BufRead, mut f: impl FnMut(&[u8])) {
+    let colorspace = Regex::new(r"\d").unwrap();
+
+    while let Some(seq) = reader.by_ref().lines().nth(1) {
+        let seq = seq.unwrap();
+        let quals = reader.by_ref().lines().nth(1).unwrap().unwrap();
+
+        let (seq, quals) = (&seq[..TRIM], &quals[..TRIM]);
+        assert!(!colorspace.is_match(seq));
+        let avg = quals.as_bytes().iter().map(|&q| (q - 33) as usize).sum::<usize>() / quals.len();
+        if avg >= 20 {
+            let seq = seq.to_string();
+            f(seq.as_bytes());
+        }
+    }
+}
+
+fn byte_parse(reader: impl BufRead, mut f: impl FnMut(&[u8])) {
+    let mut reader = FASTQReader::new(SampleArgs {
+        min_phred_score: 20,
+        trimmed_length: TRIM,
+        ..SampleArgs::default()
+    }, reader);
+
+    while let Some(seq) = reader.next_read().unwrap() {
+        f(seq);
+    }
+}
+
+fn bench_parse(c: &mut Criterion) {
+    let data = fastq();
+
+    // Parsing and filtering reads only
+    let mut group = c.benchmark_group("parse");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.bench_function("lines", |b| b.iter(|| {
+        let mut len = 0;
+        legacy_parse(Cursor::new(black_box(&data)), |seq| len += seq.len());
+        len
+    }));
+    group.bench_function("bytes", |b| b.iter(|| {
+        let mut len = 0;
+        byte_parse(Cursor::new(black_box(&data)), |seq| len += seq.len());
+        len
+    }));
+    group.finish();
+
+    // Parsing reads and extracting their base compositions
+    let mut group = c.benchmark_group("extract");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.bench_function("lines", |b| b.iter(|| {
+        let mut comp = BaseComp::init(TRIM);
+        legacy_parse(Cursor::new(black_box(&data)), |seq| comp.extract(seq).unwrap());
+        comp
+    }));
+    group.bench_function("bytes", |b| b.iter(|| {
+        let mut comp = BaseComp::init(TRIM);
+        byte_parse(Cursor::new(black_box(&data)), |seq| comp.extract(seq).unwrap());
+        comp
+    }));
+    group.finish();
+
+    // Whole runs, sampling reads (so each one replaced in the reservoir is copied) or extracting all of them
+    let args = SampleArgs {min_phred_score: 20, trimmed_length: TRIM, seed: Some(1), ..SampleArgs::default()};
+    let mut group = c.benchmark_group("run");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.bench_function("sampled", |b| b.iter(|| {
+        let args = SampleArgs {target_read_count: Some(READS as u64 / 10), ..args};
+        run(FASTQReader::new(args, Cursor::new(black_box(&data)))).unwrap()
+    }));
+    group.bench_function("all", |b| b.iter(|| {
+        let args = SampleArgs {target_read_count: None, ..args};
+        run(FASTQReader::new(args, Cursor::new(black_box(&data)))).unwrap()
+    }));
+    group.bench_function("all_4_threads", |b| b.iter(|| {
+        let args = SampleArgs {target_read_count: None, threads: 4, ..args};
+        run(FASTQReader::new(args, Cursor::new(black_box(&data)))).unwrap()
+    }));
+    group.finish();
+}
+
+criterion_group!(benches, bench_parse);
+criterion_main!(benches);
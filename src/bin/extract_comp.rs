@@ -0,0 +1,405 @@
+use fastq2comp::extract_comp::{Alphabet, FASTQReader, InputFormat, Output, PairedFASTQReader, ParseMode, QualityEncoding, QualityFilter, Rounding, SampleArgs, TableLayout, TableOptions, format_table, run, run_paired};
+use fastq2comp::{io_utils, Fastq2CompError};
+
+use std::fs::File;
+use std::io::{self, BufReader, BufRead, Read, Write};
+use std::path::PathBuf;
+use structopt::StructOpt;
+use structopt::clap::{AppSettings, ArgGroup};
+
+#[cfg(test)]
+mod test_cli {
+    use super::*;
+    use fastq2comp::extract_comp::AmbiguousBases;
+
+    #[test]
+    fn test_pipeline_args() {
+        // Invocation used by data/download-extract/download-extract.sh
+        let cli = Cli::from_iter_safe(
+            ["extract_comp", "--stdin", "--stdout", "--tsv", "--trim", "50", "100000"].iter()
+        ).unwrap();
+
+        assert!(cli.stdin && cli.stdout && cli.tsv);
+        let options = cli.table_options().unwrap();
+        assert_eq!((options.layout, options.header, options.delimiter), (TableLayout::Wide, false, '\t'));
+        assert_eq!(cli.output_file(), None);
+        assert_eq!(cli.input_file(), None);
+
+        let args = cli.sample_args();
+        assert_eq!(args.trimmed_length, 50);
+        assert_eq!(args.target_read_count, Some(100000));
+        assert_eq!(args.n_content, None);
+        assert_eq!(args.parse_mode, ParseMode::Strict);
+        assert_eq!(args.quality_encoding, None);
+        assert!(!args.quality_profile);
+        assert_eq!(args.alphabet, Alphabet::default());
+        assert!(!args.colorspace);
+        assert_eq!(args.kmer_length, None);
+
+        let cli = Cli::from_iter_safe(["extract_comp", "--stdin", "--stdout", "--alphabet", "lowercase,iupac-other"].iter()).unwrap();
+        assert_eq!(cli.sample_args().alphabet, Alphabet {fold_lowercase: true, u_as_t: false, ambiguous: AmbiguousBases::Other});
+        assert!(Cli::from_iter_safe(["extract_comp", "--stdin", "--stdout", "--alphabet", "rna"].iter()).is_err());
+
+        let cli = Cli::from_iter_safe(["extract_comp", "--stdin", "--stdout", "--all-reads", "--threads", "4"].iter()).unwrap();
+        assert_eq!(cli.sample_args().target_read_count, None);
+        assert_eq!(cli.sample_args().threads, 4);
+
+        let cli = Cli::from_iter_safe(["extract_comp", "--stdin", "--stdout", "--kmer-length", "2"].iter()).unwrap();
+        assert_eq!(cli.sample_args().kmer_length, Some(2));
+
+        let cli = Cli::from_iter_safe(["extract_comp", "--stdin", "--stdout", "--quality-encoding", "illumina13"].iter()).unwrap();
+        assert_eq!(cli.sample_args().quality_encoding, Some(QualityEncoding::Illumina13));
+        assert!(Cli::from_iter_safe(["extract_comp", "--stdin", "--stdout", "--quality-encoding", "phred"].iter()).is_err());
+
+        let cli = Cli::from_iter_safe(["extract_comp", "--stdin", "--stdout", "--quality-filter", "max_expected_errors=1.5"].iter()).unwrap();
+        assert_eq!(cli.sample_args().quality_filter, QualityFilter::MaxExpectedErrors(1.5));
+        assert_eq!(args.quality_filter, QualityFilter::MeanErrorProbability);
+    }
+
+    #[test]
+    fn test_conflicting_args() {
+        // input can't come from both a file and stdin
+        assert!(Cli::from_iter_safe(["extract_comp", "--stdin", "-i", "in.fastq", "--stdout"].iter()).is_err());
+        // some output must be given
+        assert!(Cli::from_iter_safe(["extract_comp", "--stdin"].iter()).is_err());
+        // output can't go to both a file and stdout
+        assert!(Cli::from_iter_safe(["extract_comp", "--stdin", "--stdout", "-o", "out.json"].iter()).is_err());
+        // only one table format at a time, and table options need a table format
+        assert!(Cli::from_iter_safe(["extract_comp", "--stdin", "--stdout", "--tsv", "--csv"].iter()).is_err());
+        assert!(Cli::from_iter_safe(["extract_comp", "--stdin", "--stdout", "--header"].iter()).is_err());
+    }
+
+    #[test]
+    fn test_table_args() {
+        let cli = Cli::from_iter_safe(["extract_comp", "--stdin", "--stdout", "--csv", "--long", "--header"].iter()).unwrap();
+        let options = cli.table_options().unwrap();
+        assert_eq!((options.layout, options.header, options.delimiter), (TableLayout::Long, true, ','));
+
+        let cli = Cli::from_iter_safe(["extract_comp", "--stdin", "--stdout", "--csv", "--delimiter", ";"].iter()).unwrap();
+        assert_eq!(cli.table_options().unwrap().delimiter, ';');
+
+        let cli = Cli::from_iter_safe(["extract_comp", "--stdin", "--stdout"].iter()).unwrap();
+        assert!(cli.table_options().is_none());
+    }
+
+    #[test]
+    fn test_paired_args() {
+        let cli = Cli::from_iter_safe(["extract_comp", "-i", "r1.fq", "--input2", "r2.fq", "--stdout"].iter()).unwrap();
+        assert!(cli.paired());
+        assert_eq!(cli.input2, Some(PathBuf::from("r2.fq")));
+
+        let cli = Cli::from_iter_safe(["extract_comp", "--stdin", "--interleaved", "--stdout"].iter()).unwrap();
+        assert!(cli.paired());
+
+        // read 2 needs read 1 in a file, and paired output is JSON only
+        assert!(Cli::from_iter_safe(["extract_comp", "--stdin", "--input2", "r2.fq", "--stdout"].iter()).is_err());
+        assert!(Cli::from_iter_safe(["extract_comp", "--stdin", "--interleaved", "--stdout", "--tsv"].iter()).is_err());
+        // paired-end input is always parsed strictly
+        assert!(Cli::from_iter_safe(["extract_comp", "-i", "r1.fq", "--input2", "r2.fq", "--stdout", "--lenient"].iter()).is_err());
+        assert!(Cli::from_iter_safe(["extract_comp", "--stdin", "--interleaved", "--stdout", "--lenient"].iter()).is_err());
+    }
+
+    #[test]
+    fn test_merge_args() {
+        let cli = Cli::from_iter_safe(["extract_comp", "merge", "--stdout", "a.json", "b.json"].iter()).unwrap();
+        match cli.cmd {
+            Some(Command::Merge {inputs, stdout, ..}) => {
+                assert_eq!(inputs, vec![PathBuf::from("a.json"), PathBuf::from("b.json")]);
+                assert!(stdout);
+            },
+            cmd => panic!("Expected merge subcommand, got {:?}", cmd),
+        }
+
+        // at least one input is needed
+        assert!(Cli::from_iter_safe(["extract_comp", "merge", "--stdout"].iter()).is_err());
+    }
+}
+
+/// Extracts the per-position base composition of a sample of reads from a FASTQ file.
+/// Compressed, FASTA and unaligned single-end SAM/BAM input are detected automatically.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "extract_comp", setting = AppSettings::SubcommandsNegateReqs, setting = AppSettings::ArgsNegateSubcommands)]
+#[structopt(group = ArgGroup::with_name("table"))]
+struct Cli {
+    #[structopt(subcommand)]
+    cmd: Option<Command>,
+
+    /// Input FASTQ, FASTA, SAM or BAM file (plain, gzip, or bzip2/xz/zstd if built with those features)
+    #[structopt(short, long, parse(from_os_str), required_unless = "stdin", conflicts_with = "stdin")]
+    input: Option<PathBuf>,
+
+    /// Read input from stdin
+    #[structopt(long)]
+    stdin: bool,
+
+    /// Input FASTQ file holding read 2 of paired-end data, read 1 being in the main input.
+    /// Output then holds separate base compositions of read 1 and read 2.
+    #[structopt(long, parse(from_os_str), requires = "input", conflicts_with_all = &["interleaved", "table"])]
+    input2: Option<PathBuf>,
+
+    /// Input holds interleaved paired-end data, each read 1 followed by its read 2
+    #[structopt(long, conflicts_with = "table")]
+    interleaved: bool,
+
+    /// Output file, appended to if it already exists
+    #[structopt(short, long, parse(from_os_str), required_unless = "stdout", conflicts_with = "stdout")]
+    output: Option<PathBuf>,
+
+    /// Write output to stdout
+    #[structopt(long)]
+    stdout: bool,
+
+    /// Output base compositions as a single TSV row, columns in `A C G T N` order, instead of JSON
+    #[structopt(long, group = "table")]
+    tsv: bool,
+
+    /// Output base compositions as CSV instead of JSON, laid out as with --tsv
+    #[structopt(long, group = "table")]
+    csv: bool,
+
+    /// Output a header row for TSV/CSV
+    #[structopt(long, requires = "table")]
+    header: bool,
+
+    /// Output TSV/CSV in long format, with one row per base per column: `pos base count percent`
+    #[structopt(long, requires = "table")]
+    long: bool,
+
+    /// Field delimiter for TSV/CSV, overriding the default tab or comma
+    #[structopt(long, requires = "table")]
+    delimiter: Option<char>,
+
+    /// Trims each read to given length, discarding shorter reads. Set to 0 for no trimming.
+    #[structopt(short, long, default_value = "50")]
+    trim: usize,
+
+    /// Minimum Phred score of reads to be sampled, as judged by --quality-filter
+    #[structopt(long, default_value = "0")]
+    min_phred_score: usize,
+
+    /// How qualities of reads are judged: mean_error_probability (mean error probability of bases as a Phred score),
+    /// min_base_quality (lowest Phred score of any base), fraction_above=F (fraction F of bases have at least --min-phred-score)
+    /// or max_expected_errors=E (sum of error probabilities of bases at most E, ignoring --min-phred-score)
+    #[structopt(long, default_value = "mean_error_probability")]
+    quality_filter: QualityFilter,
+
+    /// Encoding of qualities: sanger (Phred+33, also Illumina 1.8+), illumina13 (Phred+64, Illumina 1.3 to 1.7) or solexa.
+    /// Detected from the first records of input if not given.
+    #[structopt(long)]
+    quality_encoding: Option<QualityEncoding>,
+
+    /// Also output the mean, median and quartiles of qualities at each position, and the mean quality of each base there
+    #[structopt(long)]
+    quality_profile: bool,
+
+    /// Also output the composition of k-mers of this length (1 to 6) starting at each position, e.g. 2 for dinucleotides.
+    /// Only included in JSON output
+    #[structopt(long)]
+    kmer_length: Option<usize>,
+
+    /// Maximum number of N's allowed in sampled reads
+    #[structopt(long)]
+    max_n: Option<usize>,
+
+    /// Skip malformed FASTQ records instead of stopping with an error. Not supported for paired-end input
+    #[structopt(long, conflicts_with_all = &["input2", "interleaved"])]
+    lenient: bool,
+
+    /// Round percentages so each column sums to exactly 100, instead of rounding down
+    #[structopt(long)]
+    round_to_100: bool,
+
+    /// Accept characters other than ATGCN in reads, as a comma-separated list of: lowercase (count as uppercase),
+    /// u (count U as T), and iupac-n or iupac-other (count IUPAC ambiguity codes and '.' as N, or separately as other)
+    #[structopt(long, default_value = "")]
+    alphabet: Alphabet,
+
+    /// Read FASTQ or FASTA input as SOLiD colorspace (a primer base followed by colors), converting it to base space
+    #[structopt(long)]
+    colorspace: bool,
+
+    /// Seed for sampling reads, making the output reproducible
+    #[structopt(long)]
+    seed: Option<u64>,
+
+    /// Fail if fewer reads than this could be sampled
+    #[structopt(long)]
+    min_reads: Option<u64>,
+
+    /// Use every read in the input instead of a random sample
+    #[structopt(long)]
+    all_reads: bool,
+
+    /// Threads used for decompressing BGZF input and parsing and counting reads
+    #[structopt(long, default_value = "1")]
+    threads: usize,
+
+    /// Number of reads to sample
+    #[structopt(default_value = "100000")]
+    target_read_count: u64,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Merges base compositions (JSON output of extract_comp), e.g. of several lanes of a library
+    Merge {
+        /// JSON files to merge
+        #[structopt(parse(from_os_str), required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// Output file, appended to if it already exists
+        #[structopt(short, long, parse(from_os_str), required_unless = "stdout", conflicts_with = "stdout")]
+        output: Option<PathBuf>,
+
+        /// Write output to stdout
+        #[structopt(long)]
+        stdout: bool,
+
+        /// Round percentages so each column sums to exactly 100, instead of rounding down
+        #[structopt(long)]
+        round_to_100: bool,
+    },
+}
+
+impl Cli {
+    fn sample_args(&self) -> SampleArgs {
+        SampleArgs {
+            target_read_count: if self.all_reads {None} else {Some(self.target_read_count)},
+            min_phred_score: self.min_phred_score,
+            quality_filter: self.quality_filter,
+            n_content: self.max_n,
+            trimmed_length: self.trim,
+            parse_mode: if self.lenient {ParseMode::Lenient} else {ParseMode::Strict},
+            seed: self.seed,
+            min_read_count: self.min_reads,
+            rounding: if self.round_to_100 {Rounding::LargestRemainder} else {Rounding::Truncate},
+            alphabet: self.alphabet,
+            colorspace: self.colorspace,
+            quality_encoding: self.quality_encoding,
+            quality_profile: self.quality_profile,
+            kmer_length: self.kmer_length,
+            threads: self.threads,
+        }
+    }
+
+    /// Returns `None` if output should be JSON
+    fn table_options(&self) -> Option<TableOptions> {
+        if !self.tsv && !self.csv {
+            return None;
+        }
+
+        Some(TableOptions {
+            layout: if self.long {TableLayout::Long} else {TableLayout::Wide},
+            header: self.header,
+            delimiter: self.delimiter.unwrap_or(if self.csv {','} else {'\t'}),
+        })
+    }
+
+    /// Returns `None` if input should be read from stdin
+    fn input_file(&self) -> Option<&PathBuf> {
+        if self.stdin { None } else { self.input.as_ref() }
+    }
+
+    /// Returns `true` if input is paired-end
+    fn paired(&self) -> bool {
+        self.input2.is_some() || self.interleaved
+    }
+
+    /// Returns `None` if output should be written to stdout
+    fn output_file(&self) -> Option<PathBuf> {
+        if self.stdout { None } else { self.output.clone() }
+    }
+}
+
+/// Opens input file, or stdin if `path` is `None`, decompressing it if it is compressed.
+/// BGZF input is decompressed using `threads` threads.
+fn open_input(path: Option<&PathBuf>, threads: usize) -> Result<Box<dyn BufRead>, Fastq2CompError> {
+    let input: Box<dyn BufRead> = match path {
+        Some(path) => Box::new(BufReader::new(File::open(path)?)),
+        None => Box::new(BufReader::new(io::stdin())),
+    };
+    Ok(io_utils::parallel_decompressed_reader(input, threads)?)
+}
+
+fn extract(cli: Cli) -> Result<(), Fastq2CompError> {
+    let mut reader = open_input(cli.input_file(), cli.threads)?;
+    let sample_args = cli.sample_args();
+    let mut writer = io_utils::get_writer(&cli.output_file())?;
+
+    let (comp, r2_comp) = if cli.paired() {
+        let fastq_reader = match &cli.input2 {
+            Some(path) => PairedFASTQReader::new(sample_args, reader, open_input(Some(path), cli.threads)?),
+            None => PairedFASTQReader::interleaved(sample_args, reader),
+        };
+        let (r1_comp, r2_comp) = run_paired(fastq_reader)?;
+        (r1_comp, Some(r2_comp))
+    } else {
+        let comp = match InputFormat::detect(reader.fill_buf()?) {
+            InputFormat::Fastq => run(FASTQReader::new(sample_args, reader))?,
+            InputFormat::Fasta => run(FASTQReader::fasta(sample_args, reader))?,
+            InputFormat::Sam => run(FASTQReader::sam(sample_args, reader))?,
+            InputFormat::Bam => run(FASTQReader::bam(sample_args, reader))?,
+        };
+        (comp, None)
+    };
+
+    if let Some(options) = cli.table_options() {
+        writeln!(writer, "{}", format_table(&comp, &options))?;
+        eprintln!("Reads read: {}", comp.reads_read());
+    } else {
+        let mut output = Output::new(comp, sample_args);
+        output.comp_r2 = r2_comp;
+        output.inputs.push(match cli.input_file() {
+            Some(path) => path.to_string_lossy().into_owned(),
+            None => "-".to_string(),
+        });
+        if let Some(path) = &cli.input2 {
+            output.inputs.push(path.to_string_lossy().into_owned());
+        }
+        writeln!(writer, "{}", output.to_json()?)?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// Merges JSON outputs, which must have been extracted with compatible `SampleArgs` (see `Output::merge`).
+fn merge(inputs: &[PathBuf], output: Option<PathBuf>, rounding: Rounding) -> Result<(), Fastq2CompError> {
+    let mut merged: Option<Output> = None;
+    for path in inputs {
+        let mut json = String::new();
+        File::open(path)?.read_to_string(&mut json)?;
+        let output = Output::from_json(&json)?;
+
+        match &mut merged {
+            Some(merged) => merged.merge(output)?,
+            None => merged = Some(output),
+        }
+    }
+    let mut merged = merged.ok_or(Fastq2CompError::EmptyInput)?;
+    merged.compute_percentages(rounding);
+    merged.sample_args.rounding = rounding;
+
+    let mut writer = io_utils::get_writer(&output)?;
+    writeln!(writer, "{}", merged.to_json()?)?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn main() {
+    let cli = Cli::from_args();
+
+    let res = match &cli.cmd {
+        Some(Command::Merge {inputs, output, stdout, round_to_100}) => merge(
+            inputs,
+            if *stdout {None} else {output.clone()},
+            if *round_to_100 {Rounding::LargestRemainder} else {Rounding::Truncate},
+        ),
+        None => extract(cli),
+    };
+
+    if let Err(e) = res {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,107 @@
+use std::{error, fmt, io};
+
+/// Errors which can occur while reading FASTQ data and extracting base compositions.
+#[derive(Debug)]
+pub enum Fastq2CompError {
+    /// Error while reading input or writing output
+    Io(io::Error),
+    /// FASTQ (or FASTA) record could not be parsed. `record` is the 1-based index of the record in the input,
+    /// and `offset` the byte offset of the start of the record.
+    MalformedRecord { record: u64, offset: u64, reason: String },
+    /// Character which isn't accepted by the alphabet in use (by default, anything but `ATGCN`) found in a read,
+    /// at (1-based) column `pos` of (1-based) `record`. `record` is 0 for reads passed to [`crate::BaseComp::extract`] directly.
+    InvalidBase { base: char, pos: usize, record: u64 },
+    /// Quality character which is impossible in the quality encoding of the input found in (1-based) `record`.
+    InvalidQuality { qual: char, record: u64 },
+    /// Numbers found in the sequence of a record, which probably means the input is SOLiD colorspace data,
+    /// which is only read with colorspace conversion enabled.
+    Colorspace { record: u64 },
+    /// No reads were available to extract base compositions from.
+    EmptyInput,
+    /// Fewer reads than the required minimum were available to extract base compositions from.
+    TooFewReads { reads: u64, min: u64 },
+    /// Error while converting base compositions to or from JSON
+    Json(serde_json::Error),
+    /// JSON output was written with a schema version other than the supported one.
+    /// `found` is `None` for output from before the schema was versioned.
+    IncompatibleSchema { found: Option<u32>, supported: u32 },
+    /// Mates of paired-end input do not match up, at the 1-based `record` of read 1.
+    /// Either one input ended before the other, or the read names differ.
+    UnpairedRecords { record: u64, reason: String },
+    /// Paired-end and single-end base compositions were merged.
+    MixedPairing,
+    /// Read 1 or read 2 of a pair found at (1-based) `record` of SAM/BAM input, which is only read as single-end.
+    PairedSam { record: u64 },
+    /// k-mer length outside of 1 to `max` requested.
+    InvalidKmerLength { k: usize, max: usize },
+    /// Base compositions with k-mer compositions of differing lengths `k` and `other` were merged,
+    /// where `None` means no k-mers were counted.
+    MismatchedKmerLength { k: Option<usize>, other: Option<usize> },
+    /// Base compositions extracted with differing values of the `SampleArgs` field named `field`,
+    /// which changes which reads or bases are counted, were merged.
+    MismatchedSampleArgs { field: &'static str },
+}
+
+fn kmer_length(k: &Option<usize>) -> String {
+    k.map_or("none".to_string(), |k| k.to_string())
+}
+
+impl fmt::Display for Fastq2CompError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fastq2CompError::Io(e) => write!(f, "I/O error: {}", e),
+            Fastq2CompError::MalformedRecord { record, offset, reason } =>
+                write!(f, "Malformed record #{} (at byte {}): {}", record, offset, reason),
+            Fastq2CompError::InvalidBase { base, pos, record: 0 } =>
+                write!(f, "Invalid character {:?} found in read at position {}", base, pos),
+            Fastq2CompError::InvalidBase { base, pos, record } =>
+                write!(f, "Invalid character {:?} found in record #{} at position {}", base, record, pos),
+            Fastq2CompError::InvalidQuality { qual, record } =>
+                write!(f, "Invalid quality character {:?} found in record #{} for the quality encoding of input", qual, record),
+            Fastq2CompError::Colorspace { record } =>
+                write!(f, "Found numbers in record #{} - this is probably colorspace, which is only read with colorspace conversion enabled", record),
+            Fastq2CompError::EmptyInput => write!(f, "No reads could be sampled from input"),
+            Fastq2CompError::TooFewReads { reads, min } =>
+                write!(f, "Only {} reads could be sampled from input, at least {} required", reads, min),
+            Fastq2CompError::Json(e) => write!(f, "Error converting base compositions to JSON: {}", e),
+            Fastq2CompError::IncompatibleSchema { found: Some(found), supported } =>
+                write!(f, "JSON output has schema version {}, only version {} is supported", found, supported),
+            Fastq2CompError::IncompatibleSchema { found: None, supported } =>
+                write!(f, "JSON output has no schema version, only version {} is supported", supported),
+            Fastq2CompError::UnpairedRecords { record, reason } =>
+                write!(f, "Mates of paired FASTQ record #{} do not match: {}", record, reason),
+            Fastq2CompError::MixedPairing => write!(f, "Paired-end and single-end base compositions cannot be merged"),
+            Fastq2CompError::PairedSam { record } =>
+                write!(f, "Record #{} is a mate of a pair, paired-end SAM/BAM input is not supported - \
+                    convert it to FASTQ (e.g. samtools fastq -1 r1.fq -2 r2.fq) and pass read 2 with --input2", record),
+            Fastq2CompError::InvalidKmerLength { k, max } =>
+                write!(f, "k-mer length {} is not supported, it must be between 1 and {}", k, max),
+            Fastq2CompError::MismatchedKmerLength { k, other } =>
+                write!(f, "Base compositions with k-mers of length {} and {} cannot be merged", kmer_length(k), kmer_length(other)),
+            Fastq2CompError::MismatchedSampleArgs { field } =>
+                write!(f, "Base compositions extracted with differing {} cannot be merged", field),
+        }
+    }
+}
+
+impl error::Error for Fastq2CompError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Fastq2CompError::Io(e) => Some(e),
+            Fastq2CompError::Json(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Fastq2CompError {
+    fn from(e: io::Error) -> Self {
+        Fastq2CompError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for Fastq2CompError {
+    fn from(e: serde_json::Error) -> Self {
+        Fastq2CompError::Json(e)
+    }
+}
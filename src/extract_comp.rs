@@ -1,5 +1,7 @@
-use std::io::BufRead;
-use crate::BaseComp;
+use std::io::{self, BufRead};
+use crate::{BaseComp, BaseCompCol, Fastq2CompError};
+use crate::kmer_comp::KmerComp;
+use serde::{Deserialize, Serialize};
 
 #[cfg(test)]
 mod test_check_read {
@@ -10,19 +12,159 @@ mod test_check_read {
     fn test_check_colorspace() {
         let mut read = FASTQRead::new(6);
         let mut reader = return_reader(b"@\nAT1CGN\n+\n!!!!!!");
-        read.read_fastq(&mut reader);
+        read.read_fastq(&mut reader).unwrap();
+
+        assert!(FASTQRead::check_colorspace(b"AT1CGN"));
+        assert!(matches!(
+            read.check_read(&SampleArgs {trimmed_length: 0, ..SampleArgs::default()}),
+            Err(Fastq2CompError::Colorspace {record: 1})
+        ));
+    }
+
+    #[test]
+    fn test_decode_colorspace() {
+        let decode = |read: &[u8]| {
+            let mut seq = read.to_vec();
+            decode_colorspace(&mut seq).map(|_| seq)
+        };
+        assert_eq!(decode(b"T0123"), Ok(b"TGAT".to_vec()));
+        assert_eq!(decode(b"a3210"), Ok(b"TCAA".to_vec()));
+        // bases after a missing color can't be decoded
+        assert_eq!(decode(b"G01.2"), Ok(b"GTNN".to_vec()));
+        assert_eq!(decode(b""), Ok(Vec::new()));
+        assert_eq!(decode(b"N012"), Err(b'N'));
+        assert_eq!(decode(b"T01A2"), Err(b'A'));
+    }
+
+    #[test]
+    fn test_malformed_records() {
+        // (input, record index, byte offset of record)
+        let cases: [(&[u8], u64, u64); 4] = [
+            (b"@r1\nACGT\n+\nIIII\nr2\nACGT\n+\nIIII", 2, 16), // no '@'
+            (b"@r1\nACGT\n-\nIIII", 1, 0), // no '+'
+            (b"@r1\nACGT\n+\nIIII\r\n@r2\r\nACGT\r\n+\r\nIII\r\n", 2, 17), // length mismatch
+            (b"@r1\nACGT\n+\nIIII\n\n@r2\nACGT\n", 2, 17), // truncated
+        ];
+
+        for (input, record, offset) in cases.iter() {
+            let mut read = FASTQRead::new(4);
+            let mut reader = return_reader(input);
+            let res = (|| {while read.read_fastq(&mut reader)?.is_some() {}; Ok(())})();
+
+            match res {
+                Err(Fastq2CompError::MalformedRecord {record: r, offset: o, ..}) => assert_eq!((r, o), (*record, *offset)),
+                r => panic!("Expected malformed record error, got {:?}", r)
+            }
+        }
+    }
+
+    #[test]
+    fn test_lenient_resync() {
+        let mut read = FASTQRead::new(4);
+        read.mode = ParseMode::Lenient;
+        let mut reader = return_reader(
+br"@r1
+ACGT
++
+III
+@r2
+ACGA
+@r3
+ACGC
++
+IIII
+garbage
+@r4
+ACGG
++
+IIII
+");
+        let mut seqs = Vec::new();
+        while read.read_fastq(&mut reader).unwrap().is_some() {
+            seqs.push(read.seq.clone());
+        }
+
+        // r1 (length mismatch), r2 (no separator, so not taken as a header) and the line of garbage are skipped
+        assert_eq!(seqs, vec![b"ACGC".to_vec(), b"ACGG".to_vec()]);
+        assert_eq!((read.records_skipped, read.record), (3, 5));
+
+        // adjacent malformed records are counted separately, quality lines starting with '@' aren't counted
+        let mut read = FASTQRead::new(4);
+        read.mode = ParseMode::Lenient;
+        let mut reader = return_reader(b"@r1\nACG\n+\n@I\n@r2\n+\nII\n@r3\n@r4\nACGT\n+\nIIII\n@r5\nAC\n");
+        assert!(read.read_fastq(&mut reader).unwrap().is_some());
+        assert_eq!((&read.seq[..], &read.header[..], read.record, read.records_skipped), (&b"ACGT"[..], &b"@r4"[..], 4, 3));
+        // a record cut short by the end of input is counted too
+        assert!(read.read_fastq(&mut reader).unwrap().is_none());
+        assert_eq!(read.records_skipped, 4);
+
+        // quality lines starting with '@' aren't taken as headers
+        let mut read = FASTQRead::new(4);
+        read.mode = ParseMode::Lenient;
+        let mut reader = return_reader(b"garbage\nACGT\n+\n@III\n@r2\nACGA\n+\r\n@@@@\r\n@r3\nACGC\n+\nIIII\n@r4\nACGG\n");
+        let mut records = Vec::new();
+        while read.read_fastq(&mut reader).unwrap().is_some() {
+            records.push((read.seq.clone(), read.quals.clone(), read.record_offset));
+        }
+        assert_eq!(records, vec![(b"ACGA".to_vec(), b"@@@@".to_vec(), 20), (b"ACGC".to_vec(), b"IIII".to_vec(), 38)]);
+        // the garbage, and r4 which is truncated
+        assert_eq!(read.records_skipped, 2);
+    }
 
-        assert!(read.check_colorspace("AT1CGN"))
+    #[test]
+    fn test_truncated_record() {
+        let mut read = FASTQRead::new(6);
+        let mut reader = return_reader(b"@\nACGTNN\n+\n!!!!!!\n@\nACGTNN\n");
+        assert_eq!(read.read_fastq(&mut reader).unwrap(), Some(()));
+        assert!(matches!(
+            read.read_fastq(&mut reader),
+            Err(Fastq2CompError::MalformedRecord {record: 2, ..})
+        ));
     }
 
     #[test]
     fn test_count_n() {
-        assert_eq!(FASTQRead::count_n("NNANNA"), 4)
+        assert_eq!(FASTQRead::count_n(b"NNANNA", Alphabet::default()), 4);
+        assert_eq!(FASTQRead::count_n(b"NnRA.", Alphabet::default()), 1);
+        let alphabet = Alphabet {fold_lowercase: true, ambiguous: AmbiguousBases::N, ..Alphabet::default()};
+        assert_eq!(FASTQRead::count_n(b"NnRA.", alphabet), 4);
     }
 
     #[test]
-    fn test_get_average_quality() {
-        assert_eq!(FASTQRead::get_average_quality("#{|}"), 68)
+    fn test_quality_filter() {
+        use QualityFilter::*;
+        // Q2 and Q40: arithmetic mean of Phred scores would be 21, mean error probability is Q5
+        let quals = b"#I";
+        assert_eq!(MeanErrorProbability.passes(quals, QualityEncoding::Sanger, 5), Ok(true));
+        assert_eq!(MeanErrorProbability.passes(quals, QualityEncoding::Sanger, 20), Ok(false));
+        assert_eq!(MinBaseQuality.passes(quals, QualityEncoding::Sanger, 2), Ok(true));
+        assert_eq!(MinBaseQuality.passes(quals, QualityEncoding::Sanger, 20), Ok(false));
+        assert_eq!(FractionAbove(0.5).passes(quals, QualityEncoding::Sanger, 20), Ok(true));
+        assert_eq!(FractionAbove(0.6).passes(quals, QualityEncoding::Sanger, 20), Ok(false));
+        assert_eq!(MaxExpectedErrors(1.0).passes(quals, QualityEncoding::Sanger, 20), Ok(true));
+        assert_eq!(MaxExpectedErrors(0.5).passes(quals, QualityEncoding::Sanger, 0), Ok(false));
+
+        assert_eq!(MeanErrorProbability.passes(b"h@", QualityEncoding::Illumina13, 3), Ok(true));
+        assert_eq!(MeanErrorProbability.passes(b"h@", QualityEncoding::Illumina13, 4), Ok(false));
+        // Solexa -5 is Q1
+        assert_eq!(MeanErrorProbability.passes(b";h", QualityEncoding::Solexa, 4), Ok(true));
+        assert_eq!(MeanErrorProbability.passes(b";h", QualityEncoding::Solexa, 5), Ok(false));
+        assert_eq!(MeanErrorProbability.passes(b"", QualityEncoding::Sanger, 0), Ok(true));
+
+        // would underflow in Phred+33, and is impossible in Phred+64
+        assert_eq!(MinBaseQuality.passes(b"I\x1fI", QualityEncoding::Sanger, 0), Err(0x1f));
+        assert_eq!(MaxExpectedErrors(1.0).passes(b"h?", QualityEncoding::Illumina13, 0), Err(b'?'));
+    }
+
+    #[test]
+    fn test_parse_quality_filter() {
+        assert_eq!("min_base_quality".parse(), Ok(QualityFilter::MinBaseQuality));
+        assert_eq!("fraction_above=0.9".parse(), Ok(QualityFilter::FractionAbove(0.9)));
+        assert_eq!("max_expected_errors=2".parse(), Ok(QualityFilter::MaxExpectedErrors(2.0)));
+        assert!("fraction_above=1.5".parse::<QualityFilter>().is_err());
+        assert!("fraction_above".parse::<QualityFilter>().is_err());
+        assert!("min_base_quality=20".parse::<QualityFilter>().is_err());
+        assert!("median".parse::<QualityFilter>().is_err());
     }
 
     #[test]
@@ -33,47 +175,60 @@ AAAAANNNNN
 +
 !!!!!!!!!!");
         let mut f = FASTQRead::new(5);
-        f.read_fastq(&mut reader);
+        f.read_fastq(&mut reader).unwrap();
 
         // case where read is trimmed
         let args = SampleArgs {
-            target_read_count: 1,
+            target_read_count: Some(1),
             min_phred_score: 0,
             n_content: None,
-            trimmed_length: 5
+            trimmed_length: 5,
+            ..SampleArgs::default()
         };
 
-        assert!(f.check_read(&args));
+        assert!(f.check_read(&args).unwrap());
 
         // case where read is too short for trim length
         let args = SampleArgs {
-            target_read_count: 1,
+            target_read_count: Some(1),
             min_phred_score: 0,
             n_content: None,
-            trimmed_length: 15
+            trimmed_length: 15,
+            ..SampleArgs::default()
         };
 
-        assert!(!f.check_read(&args));
+        assert!(!f.check_read(&args).unwrap());
 
         // case where too many N's
         let args = SampleArgs {
-            target_read_count: 1,
+            target_read_count: Some(1),
             min_phred_score: 0,
             n_content: Some(1),
-            trimmed_length: 0
+            trimmed_length: 0,
+            ..SampleArgs::default()
         };
 
-        assert!(!f.check_read(&args));
+        assert!(!f.check_read(&args).unwrap());
 
         // case where quality too low
         let args = SampleArgs {
-            target_read_count: 1,
+            target_read_count: Some(1),
             min_phred_score: 50,
             n_content: Some(1),
-            trimmed_length: 0
+            trimmed_length: 0,
+            ..SampleArgs::default()
+        };
+
+        assert!(!f.check_read(&args).unwrap());
+
+        // case where too many expected errors
+        let args = SampleArgs {
+            quality_filter: QualityFilter::MaxExpectedErrors(2.0),
+            trimmed_length: 0,
+            ..SampleArgs::default()
         };
 
-        assert!(!f.check_read(&args));
+        assert!(!f.check_read(&args).unwrap());
     }
 }
 
@@ -86,31 +241,58 @@ mod test_runs {
     fn test_json_run() {
         let reader = return_reader(b"@\nAAA\n+\n~~~");
         let args = SampleArgs {
-            target_read_count: 1u64,
+            target_read_count: Some(1),
             min_phred_score: 0,
             n_content: None,
-            trimmed_length: 2
+            trimmed_length: 2,
+            ..SampleArgs::default()
         };
 
-        let result = run_json( FASTQReader::new(args, reader));
+        let result = run_json( FASTQReader::new(args, reader)).unwrap();
 
         assert_eq!(
             result,
-            std::str::from_utf8(b"{\"lib\":[{\"pos\":1,\"bases\":{\"A\":100,\"T\":0,\"G\":0,\"C\":0,\"N\":0}},{\"pos\":2,\"bases\":{\"A\":100,\"T\":0,\"G\":0,\"C\":0,\"N\":0}}],\"reads_read\":1}").unwrap()
+            String::from("{\"schema_version\":1,\"tool_version\":\"") + env!("CARGO_PKG_VERSION") + "\",\"inputs\":[],\
+\"sample_args\":{\"target_read_count\":1,\"min_phred_score\":0,\"quality_filter\":\"mean_error_probability\",\"n_content\":null,\"trimmed_length\":2,\"parse_mode\":\"strict\",\
+\"seed\":null,\"min_read_count\":null,\"rounding\":\"truncate\",\"alphabet\":{\"fold_lowercase\":false,\"u_as_t\":false,\"ambiguous\":\"reject\"},\"colorspace\":false,\"quality_encoding\":\"sanger\",\"quality_profile\":false,\"kmer_length\":null},\
+\"comp\":{\"lib\":[{\"pos\":1,\"coverage\":1,\"bases\":{\"A\":1,\"T\":0,\"G\":0,\"C\":0,\"N\":0},\"percentages\":{\"A\":100,\"T\":0,\"G\":0,\"C\":0,\"N\":0},\"fractions\":{\"A\":1.0,\"T\":0.0,\"G\":0.0,\"C\":0.0,\"N\":0.0}},{\"pos\":2,\"coverage\":1,\"bases\":{\"A\":1,\"T\":0,\"G\":0,\"C\":0,\"N\":0},\"percentages\":{\"A\":100,\"T\":0,\"G\":0,\"C\":0,\"N\":0},\"fractions\":{\"A\":1.0,\"T\":0.0,\"G\":0.0,\"C\":0.0,\"N\":0.0}}],\
+\"gc_content\":{\"counts\":[1," + &"0,".repeat(99) + "0],\"fit\":null},\"reads_read\":1,\"reads_accepted\":1}}"
         );
     }
 
+    #[test]
+    fn test_json_round_trip() {
+        let reader = return_reader(b"@\nACG\n+\n~~~\n@\nACT\n+\n~~~");
+        let args = SampleArgs {target_read_count: Some(2), trimmed_length: 0, ..SampleArgs::default()};
+
+        let result = run_json( FASTQReader::new(args, reader)).unwrap();
+        let output = Output::from_json(&result).unwrap();
+        assert_eq!(output.comp.reads_read(), 2);
+        assert_eq!(output.comp.lib[2].bases, BaseCompColBases {A: 0, T: 1, G: 1, C: 0, N: 0});
+        assert_eq!(output.to_json().unwrap(), result);
+        assert_eq!(BaseComp::from_json(&result).unwrap().lib, output.comp.lib);
+
+        // unversioned, or other versions are rejected
+        let unversioned = result.replace("\"schema_version\":1,", "");
+        assert!(matches!(BaseComp::from_json(&unversioned), Err(Fastq2CompError::IncompatibleSchema {found: None, supported: 1})));
+        let newer = result.replace("\"schema_version\":1,", "\"schema_version\":2,");
+        assert!(matches!(Output::from_json(&newer), Err(Fastq2CompError::IncompatibleSchema {found: Some(2), supported: 1})));
+
+        assert!(matches!(Output::from_json("{"), Err(Fastq2CompError::Json(_))));
+    }
+
     #[test]
     fn test_tsv_run() {
         let reader = return_reader(b"@\nAAA\n+\n~~~");
         let args = SampleArgs {
-            target_read_count: 1u64,
+            target_read_count: Some(1),
             min_phred_score: 0,
             n_content: None,
-            trimmed_length: 2
+            trimmed_length: 2,
+            ..SampleArgs::default()
         };
 
-        let (result, seqs) = run_tsv( FASTQReader::new(args, reader));
+        let (result, seqs) = run_tsv( FASTQReader::new(args, reader)).unwrap();
 
         assert_eq!(
             result,
@@ -119,6 +301,35 @@ mod test_runs {
         assert_eq!(seqs, 1);
     }
 
+    #[test]
+    fn test_table_run() {
+        let reader = || return_reader(b"@\nAAC\n+\n~~~\n@\nACC\n+\n~~~");
+        let args = SampleArgs {
+            target_read_count: Some(2),
+            trimmed_length: 2,
+            ..SampleArgs::default()
+        };
+
+        let options = TableOptions {header: true, ..TableOptions::default()};
+        let (result, _) = run_table( FASTQReader::new(args, reader()), &options).unwrap();
+        assert_eq!(result, "A1\tC1\tG1\tT1\tN1\tA2\tC2\tG2\tT2\tN2\n100\t0\t0\t0\t0\t50\t50\t0\t0\t0");
+
+        let options = TableOptions {layout: TableLayout::Long, header: true, delimiter: ','};
+        let (result, _) = run_table( FASTQReader::new(args, reader()), &options).unwrap();
+        assert_eq!(result.lines().collect::<Vec<_>>(), vec![
+            "pos,base,count,percent",
+            "1,A,2,100", "1,C,0,0", "1,G,0,0", "1,T,0,0", "1,N,0,0",
+            "2,A,1,50", "2,C,1,50", "2,G,0,0", "2,T,0,0", "2,N,0,0",
+        ]);
+
+        // columns without bases have no percentages
+        let mut comp = BaseComp::with_alphabet(0, "iupac-other".parse().unwrap());
+        comp.extract(b"AR").unwrap();
+        comp.compute_percentages(Rounding::Truncate);
+        assert_eq!(format_table(&comp, &TableOptions::default()), "100\t0\t0\t0\t0\t\t\t\t\t");
+        assert_eq!(format_table(&comp, &options).lines().last(), Some("2,N,0,"));
+    }
+
     #[test]
     fn test_run () {
         let reader = return_reader(
@@ -160,15 +371,464 @@ NNNNN
 *****");
 
         let args = SampleArgs {
-            target_read_count: 8,
+            target_read_count: Some(8),
             min_phred_score: 1,
             n_content: Some(1),
-            trimmed_length: 4
+            trimmed_length: 4,
+            ..SampleArgs::default()
         };
 
-        let res = run(FASTQReader::new(args, reader));
+        let res = run(FASTQReader::new(args, reader.clone())).unwrap();
         assert_eq!(res.reads_read(), 7);
-        assert_eq!(res.lib[0].bases, BaseCompColBases {A: 28, T: 57, G: 0, C: 14, N: 0});
+        assert_eq!(res.lib[0].percentages, Some(BaseCompColBases {A: 28, T: 57, G: 0, C: 14, N: 0}));
+        assert_eq!(res.lib[0].bases, BaseCompColBases {A: 2, T: 4, G: 0, C: 1, N: 0});
+
+        let args = SampleArgs {rounding: Rounding::LargestRemainder, ..args};
+        let res = run(FASTQReader::new(args, reader)).unwrap();
+        assert_eq!(res.lib[0].percentages, Some(BaseCompColBases {A: 29, T: 57, G: 0, C: 14, N: 0}));
+        assert!((res.lib[0].fractions.unwrap().A - 2.0 / 7.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_run_errors () {
+        let args = SampleArgs {
+            target_read_count: Some(2),
+            min_phred_score: 0,
+            n_content: None,
+            trimmed_length: 0,
+            ..SampleArgs::default()
+        };
+
+        // no reads at all
+        let res = run(FASTQReader::new(args, return_reader(b"")));
+        assert!(matches!(res, Err(Fastq2CompError::EmptyInput)));
+
+        // invalid base
+        let res = run(FASTQReader::new(args, return_reader(b"@\nACGT\n+\n!!!!\n@\nACXT\n+\n!!!!")));
+        assert!(matches!(res, Err(Fastq2CompError::InvalidBase {base: 'X', pos: 3, record: 2})));
+        assert_eq!(res.unwrap_err().to_string(), "Invalid character 'X' found in record #2 at position 3");
+
+        // colorspace
+        let res = run(FASTQReader::new(args, return_reader(b"@\nACGT\n+\n!!!!\n@\nT0123\n+\n!!!!!")));
+        assert!(matches!(res, Err(Fastq2CompError::Colorspace {record: 2})));
+
+        // no reads passing checks, without sampling
+        let args = SampleArgs {target_read_count: None, min_phred_score: 50, ..args};
+        let res = run(FASTQReader::new(args, return_reader(b"@\nACGT\n+\n!!!!")));
+        assert!(matches!(res, Err(Fastq2CompError::EmptyInput)));
+
+        // reads of length 0 are no error
+        for args in [args, SampleArgs {target_read_count: Some(2), ..args}, SampleArgs {threads: 2, ..args}].iter() {
+            let comp = run(FASTQReader::new(SampleArgs {min_phred_score: 0, ..*args}, return_reader(b"@\n\n+\n\n@\n\n+\n\n"))).unwrap();
+            assert_eq!((comp.len(), comp.reads_read(), comp.reads_accepted()), (0, 2, 2));
+        }
+        let (r1, _) = run_paired(PairedFASTQReader::interleaved(SampleArgs {min_phred_score: 0, ..args}, return_reader(b"@\n\n+\n\n@\n\n+\n\n"))).unwrap();
+        assert_eq!((r1.len(), r1.reads_accepted()), (0, 1));
+    }
+
+    #[test]
+    fn test_run_variable_length () {
+        // longer read after shorter ones, and no trimming
+        let reader = return_reader(b"@\nAAC\n+\nIII\n@\nTA\n+\nII\n@\nCAGGT\n+\nIIIII\n");
+        let args = SampleArgs {
+            target_read_count: Some(10),
+            trimmed_length: 0,
+            ..SampleArgs::default()
+        };
+
+        let res = run(FASTQReader::new(args, reader)).unwrap();
+        assert_eq!(res.reads_read(), 3);
+        assert_eq!(res.len(), 5);
+        assert_eq!(res.lib[1].coverage, 3);
+        assert_eq!(res.lib[2].coverage, 2);
+        assert_eq!(res.lib[2].percentages, Some(BaseCompColBases {A: 0, T: 0, G: 50, C: 50, N: 0}));
+        assert_eq!(res.lib[4].coverage, 1);
+        assert_eq!(res.lib[4].percentages, Some(BaseCompColBases {A: 0, T: 100, G: 0, C: 0, N: 0}));
+    }
+
+    #[test]
+    fn test_run_too_few_reads () {
+        // 3 reads, one of which is filtered out
+        let reader = || return_reader(b"@\nAAAA\n+\nIIII\n@\nTTTT\n+\nIIII\n@\nCC\n+\nII\n");
+        let args = SampleArgs {
+            target_read_count: Some(10),
+            trimmed_length: 4,
+            ..SampleArgs::default()
+        };
+
+        let res = Output::from_json(&run_json(FASTQReader::new(args, reader())).unwrap()).unwrap();
+        assert_eq!(res.sample_args.target_read_count, Some(10));
+        let res = res.comp;
+        assert_eq!(res.reads_read(), 2);
+        assert_eq!(res.reads_accepted(), 2);
+        assert_eq!(res.lib[0].percentages, Some(BaseCompColBases {A: 50, T: 50, G: 0, C: 0, N: 0}));
+
+        let args = SampleArgs {min_read_count: Some(3), ..args};
+        let res = run(FASTQReader::new(args, reader()));
+        assert!(matches!(res, Err(Fastq2CompError::TooFewReads {reads: 2, min: 3})));
+    }
+
+    #[test]
+    fn test_run_all_reads () {
+        // more reads than the default target count would still all be used
+        let reader = return_reader(
+br"@
+AACAA
++
+*****
+@
+TAGGA
++
+!!!!!
+@
+TACAA
++
+*****
+@
+CCCCC
++
+*****");
+
+        let args = SampleArgs {
+            target_read_count: None,
+            min_phred_score: 1,
+            trimmed_length: 4,
+            ..SampleArgs::default()
+        };
+
+        let res = run(FASTQReader::new(args, reader)).unwrap();
+        assert_eq!(res.reads_read(), 3);
+        assert_eq!(res.len(), 4);
+        assert_eq!(res.lib[0].percentages, Some(BaseCompColBases {A: 33, T: 33, G: 0, C: 33, N: 0}));
+    }
+
+    #[test]
+    fn test_quality_encoding_run () {
+        assert_eq!(QualityEncoding::detect(b'#', b'J'), QualityEncoding::Sanger);
+        assert_eq!(QualityEncoding::detect(b'I', b'I'), QualityEncoding::Sanger);
+        assert_eq!(QualityEncoding::detect(b'B', b'h'), QualityEncoding::Illumina13);
+        assert_eq!(QualityEncoding::detect(b';', b'h'), QualityEncoding::Solexa);
+        assert_eq!(QualityEncoding::detect(b'5', b'h'), QualityEncoding::Sanger);
+        // Q42 of Illumina 1.8+, and qualities of long reads beyond anything in Phred+64
+        assert_eq!(QualityEncoding::detect(b'K', b'K'), QualityEncoding::Sanger);
+        assert_eq!(QualityEncoding::detect(b'@', b'~'), QualityEncoding::Sanger);
+
+        // Phred+64: first read has average quality 10, second 40
+        let fastq = b"@\nAAAA\n+\nJJJJ\n@\nCCCC\n+\nhhhh\n";
+        let args = SampleArgs {target_read_count: None, min_phred_score: 20, trimmed_length: 0, ..SampleArgs::default()};
+
+        let mut reader = FASTQReader::new(args, return_reader(fastq));
+        assert_eq!(reader.next_read().unwrap(), Some(&b"CCCC"[..]));
+        assert_eq!(reader.quality_encoding(), Some(QualityEncoding::Illumina13));
+        assert_eq!(reader.next_read().unwrap(), None);
+
+        // detected encoding is recorded in the output
+        let comp = run(FASTQReader::new(args, return_reader(fastq))).unwrap();
+        assert_eq!(comp.quality_encoding(), Some(QualityEncoding::Illumina13));
+        let json = Output::new(comp, args).to_json().unwrap();
+        assert!(json.contains("\"quality_encoding\":\"illumina13\""), "{}", json);
+        let (r1, _) = run_paired(PairedFASTQReader::interleaved(SampleArgs {min_phred_score: 0, ..args}, return_reader(fastq))).unwrap();
+        assert_eq!(r1.quality_encoding(), Some(QualityEncoding::Illumina13));
+
+        // given encoding is used instead of detecting it
+        let args = SampleArgs {quality_encoding: Some(QualityEncoding::Sanger), ..args};
+        let comp = run(FASTQReader::new(args, return_reader(fastq))).unwrap();
+        assert_eq!(comp.reads_read(), 2);
+
+        // quality characters below '@' are impossible in Phred+64, and below '!' in any encoding
+        let args = SampleArgs {quality_encoding: Some(QualityEncoding::Illumina13), ..args};
+        let res = run(FASTQReader::new(args, return_reader(b"@\nAAAA\n+\nhhhh\n@\nCCCC\n+\nhh5h\n")));
+        assert!(matches!(res, Err(Fastq2CompError::InvalidQuality {qual: '5', record: 2})));
+        let res = run(FASTQReader::new(SampleArgs {quality_encoding: None, ..args}, return_reader(b"@\nAAAA\n+\nII I\n")));
+        assert!(matches!(res, Err(Fastq2CompError::InvalidQuality {qual: ' ', record: 1})));
+    }
+
+    #[test]
+    fn test_quality_encoding_fallback () {
+        let args = SampleArgs {target_read_count: None, trimmed_length: 0, min_phred_score: 20, ..SampleArgs::default()};
+        let records = |quals: &str, n: usize| format!("@\nACGT\n+\n{}\n", quals).repeat(n).into_bytes();
+
+        // PacBio HiFi qualities
+        let comp = run(FASTQReader::new(args, return_reader(&records("~~~~", 2)))).unwrap();
+        assert_eq!((comp.reads_read(), comp.quality_encoding()), (2, Some(QualityEncoding::Sanger)));
+
+        // Q41 and Q42 throughout the records read ahead, then low qualities
+        for quals in ["JJJJ", "KKKK"].iter() {
+            let fastq = [records(quals, 1200), records("####", 1)].concat();
+            let comp = run(FASTQReader::new(args, return_reader(&fastq))).unwrap();
+            assert_eq!((comp.reads_read(), comp.quality_encoding()), (1200, Some(QualityEncoding::Sanger)));
+        }
+
+        // records read ahead passing for Phred+64 fall back to Phred+33 at the first quality impossible in it
+        let fastq = [records("hhhh", 1200), records("####", 1), records("IIII", 1)].concat();
+        for threads in [1, 2].iter() {
+            let comp = run(FASTQReader::new(SampleArgs {threads: *threads, ..args}, return_reader(&fastq))).unwrap();
+            assert_eq!((comp.reads_read(), comp.quality_encoding()), (1201, Some(QualityEncoding::Sanger)));
+        }
+        let fastq_paired = [records("hhhh", 1200), records("####", 2)].concat();
+        let (r1, _) = run_paired(PairedFASTQReader::interleaved(args, return_reader(&fastq_paired))).unwrap();
+        assert_eq!((r1.reads_read(), r1.quality_encoding()), (600, Some(QualityEncoding::Sanger)));
+
+        // but not from a given encoding
+        let args = SampleArgs {quality_encoding: Some(QualityEncoding::Illumina13), ..args};
+        let res = run(FASTQReader::new(args, return_reader(&fastq)));
+        assert!(matches!(res, Err(Fastq2CompError::InvalidQuality {qual: '#', record: 1201})));
+    }
+
+    #[test]
+    fn test_threaded_run () {
+        let fastq: Vec<u8> = (0..10_000).flat_map(|i| format!("@r{}\n{}\n+\n{}\n",
+            i, &"ACGTNACGTNACG"[i % 5..i % 5 + 8], &"!!!!!IIIIIIIIII"[i % 7..i % 7 + 8]
+        ).into_bytes()).collect();
+
+        for target_read_count in [None, Some(5000), Some(20_000)].iter() {
+            let args = SampleArgs {
+                target_read_count: *target_read_count,
+                min_phred_score: 20,
+                trimmed_length: 0,
+                seed: Some(1),
+                ..SampleArgs::default()
+            };
+
+            let single = run(FASTQReader::new(args, return_reader(&fastq))).unwrap();
+            let threaded = run(FASTQReader::new(SampleArgs {threads: 4, ..args}, return_reader(&fastq))).unwrap();
+            assert_eq!(threaded.lib, single.lib);
+            assert_eq!(threaded.gc_content(), single.gc_content());
+            assert!(single.gc_content().fit.is_some());
+            assert_eq!((threaded.reads_read(), threaded.reads_accepted()), (single.reads_read(), single.reads_accepted()));
+
+            let args = SampleArgs {quality_profile: true, ..args};
+            let profiled = run(FASTQReader::new(args, return_reader(&fastq))).unwrap();
+            let threaded = run(FASTQReader::new(SampleArgs {threads: 4, ..args}, return_reader(&fastq))).unwrap();
+            assert_eq!(threaded.lib, profiled.lib);
+            assert!(profiled.lib.iter().all(|col| col.quals.is_some()));
+            // the same reads are sampled with or without their qualities
+            assert!(profiled.lib.iter().zip(single.lib.iter()).all(|(a, b)| a.bases == b.bases));
+        }
+
+        // errors of worker threads are returned
+        let args = SampleArgs {target_read_count: None, trimmed_length: 0, threads: 2, ..SampleArgs::default()};
+        let res = run(FASTQReader::new(args, return_reader(b"@\nACGT\n+\nIIII\n@\nACXT\n+\nIIII\n")));
+        assert!(matches!(res, Err(Fastq2CompError::InvalidBase {base: 'X', pos: 3, record: 2})));
+    }
+
+    #[test]
+    fn test_threaded_chunks () {
+        // blank and CRLF lines, over several chunks of records parsed on workers
+        let mut fastq = Vec::new();
+        for i in 0..10_000 {
+            fastq.extend_from_slice(format!("@r{}\r\n{}\r\n+\r\nIIII\r\n", i, &"ACGTACG"[i % 4..i % 4 + 4]).as_bytes());
+            if i % 1000 == 0 {
+                fastq.extend_from_slice(b"\n\r\n");
+            }
+        }
+        let args = SampleArgs {target_read_count: None, trimmed_length: 0, kmer_length: Some(2), ..SampleArgs::default()};
+        let single = run(FASTQReader::new(args, return_reader(&fastq))).unwrap();
+        let threaded = run(FASTQReader::new(SampleArgs {threads: 3, ..args}, return_reader(&fastq))).unwrap();
+        assert_eq!(threaded.lib, single.lib);
+        assert_eq!(threaded.kmer_comp(), single.kmer_comp());
+        assert_eq!((threaded.reads_read(), threaded.reads_accepted()), (10_000, 10_000));
+
+        // errors are those of the first malformed record in input, numbered as when parsed on one thread
+        let errors = |fastq: &[u8], args: SampleArgs| {
+            let single = run(FASTQReader::new(args, return_reader(fastq))).unwrap_err();
+            let threaded = run(FASTQReader::new(SampleArgs {threads: 3, ..args}, return_reader(fastq))).unwrap_err();
+            (single.to_string(), threaded.to_string())
+        };
+        let mut malformed = fastq.clone();
+        for record in [9000, 6000].iter() {
+            let pos = malformed.windows(8).position(|w| w == format!("\n@r{}\r", record).as_bytes()).unwrap();
+            malformed[pos + 1] = b'>';
+        }
+        let (single_error, threaded_error) = errors(&malformed, args);
+        assert!(single_error.contains("#6001 "), "{}", single_error);
+        assert_eq!(threaded_error, single_error);
+
+        let (single_error, threaded_error) = errors(&fastq[..fastq.len() - 9], args);
+        assert!(single_error.contains("unexpected end of input"), "{}", single_error);
+        assert_eq!(threaded_error, single_error);
+
+        // malformed records are skipped in lenient mode, with records parsed on the calling thread
+        let args = SampleArgs {parse_mode: ParseMode::Lenient, ..args};
+        let single = run(FASTQReader::new(args, return_reader(&malformed))).unwrap();
+        let threaded = run(FASTQReader::new(SampleArgs {threads: 3, ..args}, return_reader(&malformed))).unwrap();
+        assert_eq!(threaded.lib, single.lib);
+        assert_eq!((threaded.records_skipped(), threaded.reads_read()), (2, 9998));
+    }
+
+    #[test]
+    fn test_colorspace_run () {
+        // qualities with and without one for the primer base
+        let fastq = b"@r1\nT0123\n+\n!IIII\n@r2\nG01.2\n+\nIIII\n";
+        let args = SampleArgs {target_read_count: None, trimmed_length: 0, ..SampleArgs::default()};
+        assert!(matches!(run(FASTQReader::new(args, return_reader(&fastq[..18]))), Err(Fastq2CompError::Colorspace {record: 1})));
+
+        let args = SampleArgs {colorspace: true, ..args};
+        let comp = run(FASTQReader::new(args, return_reader(fastq))).unwrap();
+        assert_eq!((comp.lib[0].bases.T, comp.lib[0].bases.G), (1, 1));
+        assert_eq!((comp.lib[2].bases.A, comp.lib[2].bases.N), (1, 1));
+
+        let comp = run(FASTQReader::fasta(args, return_reader(b">r1\nT01\n23\n>r2\nG01.2\n"))).unwrap();
+        assert_eq!(comp.lib[3].bases, BaseCompColBases {A: 0, T: 1, G: 0, C: 0, N: 1});
+
+        // base space reads aren't colorspace, and are skipped in lenient mode
+        let input = b"@r1\nACGT\n+\nIIII\n@r2\nT0123\n+\nIIII\n";
+        let res = run(FASTQReader::new(args, return_reader(input)));
+        assert!(matches!(res, Err(Fastq2CompError::MalformedRecord {record: 1, ..})));
+        let comp = run(FASTQReader::new(SampleArgs {parse_mode: ParseMode::Lenient, ..args}, return_reader(input))).unwrap();
+        assert_eq!((comp.reads_read(), comp.records_skipped()), (1, 1));
+
+        let res = run(FASTQReader::fasta(args, return_reader(b">r1\nT0123\n>r2\nACGT\n")));
+        assert!(matches!(res, Err(Fastq2CompError::MalformedRecord {record: 2, offset: 10, ..})));
+    }
+
+    #[test]
+    fn test_alphabet_run () {
+        let reader = || return_reader(b"@\nacgu\n+\nIIII\n@\nRCGT\n+\nIIII\n");
+        let args = SampleArgs {target_read_count: None, trimmed_length: 0, ..SampleArgs::default()};
+        assert!(matches!(run(FASTQReader::new(args, reader())), Err(Fastq2CompError::InvalidBase {base: 'a', pos: 1, record: 1})));
+
+        let args = SampleArgs {alphabet: "lowercase,u,iupac-other".parse().unwrap(), ..args};
+        let comp = run(FASTQReader::new(args, reader())).unwrap();
+        assert_eq!((comp.lib[0].bases.A, comp.lib[0].other), (1, 1));
+        assert_eq!(comp.lib[3].bases.T, 2);
+
+        // the alphabet is recorded in the output
+        let json = Output::new(comp, args).to_json().unwrap();
+        assert!(json.contains("\"alphabet\":{\"fold_lowercase\":true,\"u_as_t\":true,\"ambiguous\":\"other\"}"), "{}", json);
+        assert!(json.contains("\"other\":1"), "{}", json);
+        assert_eq!(Output::from_json(&json).unwrap().sample_args.alphabet, args.alphabet);
+    }
+
+    #[test]
+    fn test_quality_profile_run () {
+        let args = SampleArgs {target_read_count: None, trimmed_length: 3, quality_profile: true, ..SampleArgs::default()};
+        let comp = run(FASTQReader::new(args, return_reader(b"@\nACGT\n+\n#+5I\n@\nAACC\n+\n?I+I\n@\nAC\n+\nII\n"))).unwrap();
+        assert_eq!(comp.reads_read(), 2);
+
+        let quals = comp.lib[0].quals.as_ref().unwrap();
+        assert_eq!((quals.mean, quals.median), (Some(16.0), Some(2)));
+        let quals = comp.lib[1].quals.as_ref().unwrap();
+        assert_eq!((quals.mean, quals.lower_quartile, quals.upper_quartile), (Some(25.0), Some(10), Some(40)));
+        assert_eq!((quals.base_means.C, quals.base_means.A), (Some(10.0), Some(40.0)));
+
+        let json = Output::new(comp, args).to_json().unwrap();
+        let parsed = Output::from_json(&json).unwrap();
+        assert_eq!(parsed.comp.lib[1].quals.as_ref().unwrap().counts[40], 1);
+
+        // input without qualities has no profile
+        let comp = run(FASTQReader::fasta(args, return_reader(b">r\nACGT\n"))).unwrap();
+        assert_eq!(comp.lib[0].quals, None);
+        assert!(!Output::new(comp, args).to_json().unwrap().contains("quals"));
+    }
+
+    #[test]
+    fn test_kmer_run () {
+        let fastq = b"@\nACGT\n+\nIIII\n@\nACNT\n+\nIIII\n@\nCGA\n+\nIII\n";
+        let args = SampleArgs {target_read_count: None, trimmed_length: 0, kmer_length: Some(2), ..SampleArgs::default()};
+        let comp = run(FASTQReader::new(args, return_reader(fastq))).unwrap();
+        let kmers = comp.kmer_comp().unwrap();
+        assert_eq!((kmers.len(), kmers.reads_read()), (4, 3));
+        assert_eq!((kmers.lib[0].count(b"AC"), kmers.lib[0].count(b"CG")), (2, 1));
+        assert_eq!(kmers.lib[0].percentages.as_ref().unwrap().iter().max(), Some(&66));
+
+        // threads and sampling count the same k-mers
+        for args in [SampleArgs {threads: 2, ..args}, SampleArgs {target_read_count: Some(10), ..args}].iter() {
+            let other = run(FASTQReader::new(*args, return_reader(fastq))).unwrap();
+            assert_eq!(other.kmer_comp(), comp.kmer_comp());
+        }
+
+        let json = Output::new(comp, args).to_json().unwrap();
+        assert!(json.contains("\"kmer_length\":2"), "{}", json);
+        let mut output = Output::from_json(&json).unwrap();
+        assert_eq!(output.comp.kmer_comp().unwrap().lib[0].count(b"AC"), 2);
+
+        // k-mers of differing lengths can't be merged
+        let args = SampleArgs {kmer_length: Some(3), ..args};
+        let other = run(FASTQReader::new(args, return_reader(fastq))).unwrap();
+        assert!(matches!(output.merge(Output::new(other, args)), Err(Fastq2CompError::MismatchedKmerLength {k: Some(2), other: Some(3)})));
+
+        let (r1, r2) = run_paired(PairedFASTQReader::new(args, return_reader(fastq), return_reader(fastq))).unwrap();
+        assert_eq!(r1.kmer_comp().unwrap().lib[1].count(b"CGT"), r2.kmer_comp().unwrap().lib[1].count(b"CGT"));
+
+        for k in [0, crate::kmer_comp::MAX_K + 1].iter() {
+            let args = SampleArgs {kmer_length: Some(*k), ..args};
+            assert!(matches!(run(FASTQReader::new(args, return_reader(fastq))), Err(Fastq2CompError::InvalidKmerLength {..})));
+        }
+
+        // without a k-mer length, there is no k-mer composition
+        let comp = run(FASTQReader::new(SampleArgs {kmer_length: None, ..args}, return_reader(fastq))).unwrap();
+        assert!(comp.kmer_comp().is_none());
+    }
+
+    #[test]
+    fn test_lenient_run () {
+        let reader = return_reader(b"@\nAAA\n+\n~~\n@\nAAA\n+\n~~~");
+        let args = SampleArgs {
+            target_read_count: Some(1),
+            trimmed_length: 2,
+            parse_mode: ParseMode::Lenient,
+            ..SampleArgs::default()
+        };
+
+        let result = run_json( FASTQReader::new(args, reader)).unwrap();
+        assert!(result.ends_with("\"reads_read\":1,\"reads_accepted\":1,\"records_skipped\":1}}"), "{}", result);
+        assert_eq!(Output::from_json(&result).unwrap().comp.records_skipped(), 1);
+    }
+
+    #[test]
+    fn test_seeded_run () {
+        let mut fastq = Vec::new();
+        for i in 0..1000 {
+            fastq.extend_from_slice(format!("@\n{:010b}\n+\nIIIIIIIIII\n", i).replace('0', "A").replace('1', "C").as_bytes());
+        }
+        let args = SampleArgs {
+            target_read_count: Some(10),
+            trimmed_length: 10,
+            seed: Some(42),
+            ..SampleArgs::default()
+        };
+
+        let result = run_json(FASTQReader::new(args, return_reader(&fastq))).unwrap();
+        assert_eq!(result, run_json(FASTQReader::new(args, return_reader(&fastq))).unwrap());
+
+        let output = Output::from_json(&result).unwrap();
+        assert_eq!(output.sample_args.seed, Some(42));
+        assert_eq!(output.comp.reads_read(), 10);
+        assert_eq!(output.comp.reads_accepted(), 1000);
+
+        let args = SampleArgs {seed: Some(43), ..args};
+        assert_ne!(result, run_json(FASTQReader::new(args, return_reader(&fastq))).unwrap());
+    }
+
+    #[test]
+    fn test_merge_sample_args () {
+        let fastq = b"@\nACGT\n+\nIIII\n@\nCGTA\n+\n####\n";
+        let args = SampleArgs {trimmed_length: 4, seed: Some(1), quality_encoding: Some(QualityEncoding::Sanger), ..SampleArgs::default()};
+        let output = |args| Output::new(run(FASTQReader::new(args, return_reader(fastq))).unwrap(), args);
+
+        // seeds and quality encodings can differ, but aren't kept
+        let mut merged = output(args);
+        merged.merge(output(SampleArgs {seed: Some(2), ..args})).unwrap();
+        let mut other = output(args);
+        other.sample_args.quality_encoding = Some(QualityEncoding::Illumina13);
+        merged.merge(other).unwrap();
+        assert_eq!((merged.sample_args.seed, merged.sample_args.quality_encoding), (None, None));
+        assert_eq!(merged.comp.reads_read(), 6);
+
+        // anything changing which reads or bases are counted can't
+        for (other, field) in [
+            (SampleArgs {trimmed_length: 3, ..args}, "trimmed_length"),
+            (SampleArgs {min_phred_score: 20, ..args}, "min_phred_score"),
+            (SampleArgs {quality_filter: QualityFilter::MinBaseQuality, ..args}, "quality_filter"),
+            (SampleArgs {target_read_count: None, ..args}, "target_read_count"),
+            (SampleArgs {alphabet: Alphabet {fold_lowercase: true, ..args.alphabet}, ..args}, "alphabet"),
+        ].iter() {
+            let mut merged = output(args);
+            let result = merged.merge(output(*other));
+            assert!(matches!(result, Err(Fastq2CompError::MismatchedSampleArgs {field: f}) if f == *field), "{:?}", result);
+            assert_eq!(merged.comp.reads_read(), 2);
+        }
     }
 }
 
@@ -204,178 +864,1159 @@ IIIII
 ");
 
         let mut freader = FASTQReader::new(SampleArgs {
-            target_read_count: 2,
+            target_read_count: Some(2),
             min_phred_score: 1,
             n_content: Some(2),
-            trimmed_length: 5
+            trimmed_length: 5,
+            ..SampleArgs::default()
         }, reader);
         
-        assert_eq!(freader.next(), Some("ACGTN".to_string()));
+        assert_eq!(freader.next().unwrap().unwrap(), b"ACGTN".to_vec());
+        assert!(freader.next().is_none());
     }
-}
 
-#[derive(Debug, Clone, Copy)]
-pub struct SampleArgs {
-    /// Target sample count
-    pub target_read_count: u64,
-    /// Sets minimum average quality allowed in sampled reads.
-    pub min_phred_score: usize,
-    /// Sets maximum amount of N's allowed in sample reads. Set to none for no truncation.
-    pub n_content: Option<usize>,
-    /// Trims each sampled read to given length. Set to 0 for no trimming.
-    pub trimmed_length: usize,
-}
+    #[test]
+    fn test_next_read_crlf () {
+        let reader = return_reader(b"@r1\r\nACGTN\r\n+\r\nIIIII\r\n@r2\r\nTTGCA\r\n+\r\nIIIII");
+        let mut freader = FASTQReader::new(SampleArgs {trimmed_length: 0, ..SampleArgs::default()}, reader);
 
-impl Default for SampleArgs {
-    fn default() -> Self {
-        SampleArgs { target_read_count: 100000, min_phred_score: 0, n_content: None, trimmed_length: 50 }
+        assert_eq!(freader.next_read().unwrap(), Some(&b"ACGTN"[..]));
+        assert_eq!(freader.next_read().unwrap(), Some(&b"TTGCA"[..]));
+        assert_eq!(freader.next_read().unwrap(), None);
     }
 }
 
-use regex::Regex;
+#[cfg(test)]
+mod test_fasta {
+    use super::*;
+    use crate::test_utils::*;
 
-use lazy_static::lazy_static;
-lazy_static! {
-    // Regex for checking if seq has numbers
-    static ref SEQCOLORSPACECHECKER: Regex = Regex::new(r"\d").unwrap();
-}
+    #[test]
+    fn test_read_fasta() {
+        let mut read = FASTARead::new(4);
+        let mut reader = return_reader(b">r1 desc\nACGT\nAC\r\n\n>r2\n>r3\nGG\n;comment\nTT");
+        let mut seqs = Vec::new();
+        while read.read_fasta(&mut reader).unwrap().is_some() {
+            seqs.push(read.seq.clone());
+        }
 
-/// Abstraction for a single read of FASTQ data
-#[derive(Debug)]
-pub(crate) struct FASTQRead {
-    pub seq: String,
-    quals: String,
+        assert_eq!(seqs, vec![b"ACGTAC".to_vec(), b"".to_vec(), b"GGTT".to_vec()]);
+        assert_eq!(read.record(), 3);
+    }
+
+    #[test]
+    fn test_malformed_fasta() {
+        let input = b"ACGT\n>r1\nAC\n";
+
+        let mut read = FASTARead::new(4);
+        assert!(matches!(
+            read.read_fasta(&mut return_reader(input)),
+            Err(Fastq2CompError::MalformedRecord {record: 1, offset: 0, ..})
+        ));
+
+        let mut read = FASTARead::new(4);
+        read.mode = ParseMode::Lenient;
+        let mut reader = return_reader(input);
+        assert_eq!(read.read_fasta(&mut reader).unwrap(), Some(()));
+        assert_eq!((read.seq(), read.records_skipped()), (&b"AC"[..], 1));
+        assert_eq!(read.read_fasta(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn test_fasta_run() {
+        // quality filtering is disabled, as FASTA has no qualities
+        let args = SampleArgs {
+            target_read_count: None,
+            min_phred_score: 40,
+            n_content: Some(0),
+            trimmed_length: 2,
+            ..SampleArgs::default()
+        };
+        let reader = return_reader(b">r1\nA\nC\n>r2\nAN\n>r3\nCCC\n>r4\nA\n");
+
+        let comp = run(FASTQReader::fasta(args, reader)).unwrap();
+        assert_eq!((comp.reads_read(), comp.reads_accepted()), (2, 2));
+        assert_eq!(comp.lib[0].bases.A, 1);
+        assert_eq!(comp.lib[1].bases.C, 2);
+    }
 }
 
-use crate::exit;
+#[cfg(test)]
+mod test_input_format {
+    use super::*;
 
-impl FASTQRead {
+    #[test]
+    fn test_detect() {
+        assert_eq!(InputFormat::detect(b"@r1\nACGT\n+\nIIII\n"), InputFormat::Fastq);
+        assert_eq!(InputFormat::detect(b"@HDR\tx\nACGT\n+\nIIII\n"), InputFormat::Fastq);
+        assert_eq!(InputFormat::detect(b">r1\nACGT\n"), InputFormat::Fasta);
+        assert_eq!(InputFormat::detect(b"@HD\tVN:1.6\n"), InputFormat::Sam);
+        assert_eq!(InputFormat::detect(b"r1\t4\t*\t0\t0\t*\t*\t0\t0\tACGT\tIIII\n"), InputFormat::Sam);
+        assert_eq!(InputFormat::detect(b"BAM\x01\0\0\0\0"), InputFormat::Bam);
+        assert_eq!(InputFormat::detect(b""), InputFormat::Fastq);
+    }
+}
 
-    /// Reads a complete FASTQ statement (composed of 4 lines) into itself
-    /// - `reader`: Object implementing `std::io::BufRead` from which to read lines
-    /// - Returns `None` if EOF reached.
-    fn read_fastq (&mut self, reader: &mut impl BufRead) -> Option<()> {
-        //Skips the 1st and 3rd line resp. in 4 lines of input
-        for s in [&mut self.seq, &mut self.quals].iter_mut() {
-            **s = match reader.lines().nth(1) {
-                Some(n) => n.expect("Error reading line. Make sure UTF-8 input is supported"),
-                None => {return None},
-            }
-        }
+#[cfg(test)]
+mod test_paired {
+    use super::*;
+    use crate::{BaseCompColBases, test_utils::*};
 
-        Some(())
+    const R1: &[u8] = b"@p1/1\nACGT\n+\nIIII\n@p2/1\nAAAA\n+\nIIII\n@p3/1\nCCCC\n+\nIIII\n";
+    const R2: &[u8] = b"@p1/2\nTTTT\n+\nIIII\n@p2/2\nGG\n+\nII\n@p3/2\nGGGG\n+\nIIII\n";
+
+    #[test]
+    fn test_next_pair() {
+        let args = SampleArgs {trimmed_length: 4, ..SampleArgs::default()};
+
+        // second pair is dropped as read 2 is too short
+        let mut reader = PairedFASTQReader::new(args, return_reader(R1), return_reader(R2));
+        assert_eq!(reader.next_pair().unwrap(), Some((&b"ACGT"[..], &b"TTTT"[..])));
+        assert_eq!(reader.next_pair().unwrap(), Some((&b"CCCC"[..], &b"GGGG"[..])));
+        assert_eq!(reader.next_pair().unwrap(), None);
+        assert_eq!(reader.reads_accepted(), 2);
+
+        let interleaved = b"@p1 1:N\nACGT\n+\nIIII\n@p1 2:N\nTTTT\n+\nIIII\n";
+        let mut reader = PairedFASTQReader::interleaved(args, return_reader(interleaved));
+        assert_eq!(reader.next().unwrap().unwrap(), (b"ACGT".to_vec(), b"TTTT".to_vec()));
+        assert!(reader.next().is_none());
     }
 
-    fn new (len: usize) -> FASTQRead {
-        FASTQRead {
-            seq: String::with_capacity(len),
-            quals: String::with_capacity(len),
+    #[test]
+    fn test_paired_quality_profile() {
+        let r2 = b"@p1/2\nTTTT\n+\n####\n@p2/2\nGG\n+\nII\n@p3/2\nGGGG\n+\n5555\n";
+        for target_read_count in [None, Some(10)].iter() {
+            let args = SampleArgs {target_read_count: *target_read_count, trimmed_length: 4, quality_profile: true, ..SampleArgs::default()};
+            let (r1_comp, r2_comp) = run_paired(PairedFASTQReader::new(args, return_reader(R1), return_reader(r2))).unwrap();
+            assert_eq!(r1_comp.lib[0].quals.as_ref().unwrap().mean, Some(40.0));
+            assert_eq!(r2_comp.lib[0].quals.as_ref().unwrap().mean, Some(11.0));
         }
     }
 
-    fn count_n(seq: &str) -> usize {
-        seq.matches('N').count()
+    #[test]
+    fn test_unpaired() {
+        // with the quality encoding given, pairs aren't read ahead, so errors are returned with the pair they occur at
+        let args = SampleArgs {trimmed_length: 0, quality_encoding: Some(QualityEncoding::Sanger), ..SampleArgs::default()};
+
+        let mut reader = PairedFASTQReader::new(args, return_reader(R1), return_reader(&R2[..18]));
+        assert!(reader.next_pair().unwrap().is_some());
+        assert!(matches!(reader.next_pair(), Err(Fastq2CompError::UnpairedRecords {record: 2, ..})));
+
+        let mut reader = PairedFASTQReader::new(args, return_reader(R1), return_reader(&R2[18..]));
+        assert!(matches!(reader.next_pair(), Err(Fastq2CompError::UnpairedRecords {record: 1, ..})));
+
+        // odd number of records in interleaved input, record index counts both mates
+        let mut reader = PairedFASTQReader::interleaved(args, return_reader(R1));
+        assert!(matches!(reader.next_pair(), Err(Fastq2CompError::UnpairedRecords {record: 2, ..})));
+        assert!(matches!(reader.next_pair(), Err(Fastq2CompError::UnpairedRecords {record: 3, ..})));
+
+        // malformed records are errors even in lenient mode
+        let args = SampleArgs {parse_mode: ParseMode::Lenient, ..args};
+        let mut reader = PairedFASTQReader::new(args, return_reader(b"@p1\nAC\n+\nI\n"), return_reader(R2));
+        assert!(matches!(reader.next_pair(), Err(Fastq2CompError::MalformedRecord {record: 1, ..})));
     }
 
-    // Returns true if number is found in seq
-    fn check_colorspace(&self, seq: &str) -> bool {
-        SEQCOLORSPACECHECKER.is_match(seq)
+    #[test]
+    fn test_run_paired() {
+        let args = SampleArgs {target_read_count: Some(1), trimmed_length: 4, seed: Some(1), ..SampleArgs::default()};
+        let (r1, r2) = run_paired(PairedFASTQReader::new(args, return_reader(R1), return_reader(R2))).unwrap();
+        assert_eq!((r1.reads_read(), r1.reads_accepted()), (1, 2));
+        assert_eq!((r2.reads_read(), r2.reads_accepted()), (1, 2));
+        // mates are sampled together
+        let sampled = match r1.lib[0].bases {
+            BaseCompColBases {A: 1, ..} => BaseCompColBases {A: 0, T: 1, G: 0, C: 0, N: 0},
+            _ => BaseCompColBases {A: 0, T: 0, G: 1, C: 0, N: 0},
+        };
+        assert_eq!(r2.lib[0].bases, sampled);
+
+        let args = SampleArgs {target_read_count: None, ..args};
+        let (r1, r2) = run_paired(PairedFASTQReader::new(args, return_reader(R1), return_reader(R2))).unwrap();
+        assert_eq!(r1.lib[0].percentages, Some(BaseCompColBases {A: 50, T: 0, G: 0, C: 50, N: 0}));
+        assert_eq!(r2.lib[0].percentages, Some(BaseCompColBases {A: 0, T: 50, G: 50, C: 0, N: 0}));
+
+        let mut output = Output::paired(r1, r2, SampleArgs {parse_mode: ParseMode::Lenient, ..args});
+        assert_eq!(output.sample_args.parse_mode, ParseMode::Strict);
+        let json = output.to_json().unwrap();
+        let parsed = Output::from_json(&json).unwrap();
+        assert_eq!(parsed.comp_r2.as_ref().unwrap().lib, output.comp_r2.as_ref().unwrap().lib);
+
+        output.merge(parsed).unwrap();
+        assert_eq!(output.comp_r2.as_ref().unwrap().reads_read(), 4);
+
+        let single = run(FASTQReader::new(args, return_reader(R1))).unwrap();
+        assert!(matches!(output.merge(Output::new(single, args)), Err(Fastq2CompError::MixedPairing)));
     }
+}
+
+#[cfg(test)]
+mod test_reservoir {
+    use super::*;
+    use crate::test_utils::*;
 
-    fn get_average_quality(quals: &str) -> usize {
-        let mut qual_sum: usize = 0;
-        for char in quals.as_bytes() {
-            qual_sum += (*char as usize) - 33;
+    #[test]
+    fn test_uniform () {
+        // each of 100 items is sampled into 10 slots a tenth of the time
+        let (mut sampled, mut stored) = (vec![0; 100], 0);
+        for seed in 0..2000 {
+            let mut reservoir = Reservoir::new(10, Some(seed));
+            for i in 0..100 {
+                if let Some(slot) = reservoir.offer() {
+                    *slot = i;
+                    stored += 1;
+                }
+            }
+            for i in reservoir.into_sampled() {
+                sampled[i] += 1;
+            }
         }
+        assert!(sampled.iter().all(|n| (140..260).contains(n)), "{:?}", sampled);
+        // only sampled items are stored, about 10 * (1 + ln(100 / 10)) = 33 of each stream
+        assert!(stored < 2000 * 40, "{}", stored);
+    }
 
-        qual_sum / quals.len()
+    #[test]
+    fn test_huge_target () {
+        // slots are only allocated as items are offered
+        let mut reservoir = Reservoir::<Vec<u8>>::new(u64::MAX, None);
+        assert!(reservoir.slots.capacity() <= RESERVOIR_PREALLOCATED);
+        assert!(reservoir.offer().is_some());
+        assert_eq!(reservoir.into_sampled().len(), 1);
+
+        let fastq = b"@\nACGT\n+\nIIII\n@\nAC\n+\nII\n";
+        let args = SampleArgs {target_read_count: Some(u64::MAX), trimmed_length: 0, ..SampleArgs::default()};
+        assert_eq!(run(FASTQReader::new(args, return_reader(fastq))).unwrap().reads_read(), 2);
+        let args = SampleArgs {quality_profile: true, ..args};
+        assert_eq!(run(FASTQReader::new(args, return_reader(fastq))).unwrap().reads_read(), 2);
+        let (r1, _) = run_paired(PairedFASTQReader::interleaved(args, return_reader(fastq))).unwrap();
+        assert_eq!(r1.reads_read(), 1);
     }
+}
 
-    /// Returns trimmed string.
-    /// - In case len = None, returns string unchanged
-    /// - In case len > str len, returns Err
+/// How malformed FASTQ records are handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ParseMode {
+    /// Stop at the first malformed record with an error
+    Strict,
+    /// Skip malformed records, resuming at the next line starting with `@` which is followed by a sequence line and a `+` line.
+    /// Skipped records are counted in the output.
+    Lenient,
+}
 
-    fn trim(str: &str, len: usize) -> Result<&str, ()> {
-        match len {
-            n if n != 0 => {
-                if n > str.len() {
-                    return Err(());
-                }
-                Ok(&str[0..n])
+/// Encodings of quality scores as characters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QualityEncoding {
+    /// Phred+33, as used by Sanger, Illumina 1.8+ and SAM/BAM
+    Sanger,
+    /// Phred+64, as used by Illumina 1.3 to 1.7
+    Illumina13,
+    /// Solexa scores +64, as used by Solexa and Illumina before 1.3
+    Solexa,
+}
+
+impl QualityEncoding {
+    /// Returns the Phred score of a quality character, or `None` if the character is impossible in this encoding.
+    /// Solexa scores are converted to the equivalent Phred score.
+    pub fn phred (self, qual: u8) -> Option<u8> {
+        match self {
+            QualityEncoding::Sanger if (33..=126).contains(&qual) => Some(qual - 33),
+            QualityEncoding::Illumina13 if (64..=126).contains(&qual) => Some(qual - 64),
+            QualityEncoding::Solexa if (59..=126).contains(&qual) => {
+                let solexa = f64::from(qual) - 64.0;
+                Some((10.0 * (10f64.powf(solexa / 10.0) + 1.0).log10()).round() as u8)
             },
-            _ => Ok(&str[0..]),
+            _ => None,
         }
     }
 
-    /** Checks read according to parameters given in SampleArgs,
-    return `true` if read should be included in calculation of Base Compositions,
-    return `false` if not.
-    
-    Eg.
-    Read "N" and SampleArgs.n_content: Some(1) will return false. 
-    */
-    fn check_read(&mut self, args: &SampleArgs) -> bool {
-        let seq = FASTQRead::trim(&self.seq, args.trimmed_length);
-        let quals = FASTQRead::trim(&self.quals, args.trimmed_length);
+    /// Guesses the encoding from the lowest and highest quality characters found in input.
+    /// As Phred+33 and Phred+64 overlap, an encoding +64 is only chosen if the lowest character is impossible in Phred+33 data
+    /// of reasonable quality (below `;`), and the highest one is beyond `K` (Q42, the highest used by short read Phred+33 data),
+    /// but no higher than `i` (Q41 in Phred+64, the highest used by Illumina 1.3 to 1.7).
+    /// Anything else, e.g. long reads with qualities up to `~`, is taken to be Phred+33.
+    pub fn detect (lowest: u8, highest: u8) -> QualityEncoding {
+        match lowest {
+            _ if !(b'L'..=b'i').contains(&highest) => QualityEncoding::Sanger,
+            b';'..=b'?' => QualityEncoding::Solexa,
+            b'@'..=u8::MAX => QualityEncoding::Illumina13,
+            _ => QualityEncoding::Sanger,
+        }
+    }
+}
 
-        let (seq, quals) = match (seq, quals) {
-            (Ok(s), Ok(q)) => (s, q),
-            _ => return false,
-        };
+/// Parses the names used in JSON output, e.g. `illumina13`
+impl std::str::FromStr for QualityEncoding {
+    type Err = String;
 
-        // Check for numbers in reads
-        if self.check_colorspace(seq) {
-            eprintln!("Found numbers in reads - this is probably colorspace\n{:?}", (seq, quals));
-            exit();
+    fn from_str (s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sanger" => Ok(QualityEncoding::Sanger),
+            "illumina13" => Ok(QualityEncoding::Illumina13),
+            "solexa" => Ok(QualityEncoding::Solexa),
+            _ => Err(format!("unknown quality encoding {:?}, expected sanger, illumina13 or solexa", s)),
         }
+    }
+}
+
+/// Criteria by which reads are filtered on their qualities
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QualityFilter {
+    /// Mean error probability of bases, converted back to a Phred score, must be at least `min_phred_score`
+    MeanErrorProbability,
+    /// Every base must have a Phred score of at least `min_phred_score`
+    MinBaseQuality,
+    /// At least this fraction of bases must have a Phred score of at least `min_phred_score`
+    FractionAbove(f64),
+    /// Expected number of errors in a read, the sum of error probabilities of its bases, must be at most this.
+    /// Ignores `min_phred_score`.
+    MaxExpectedErrors(f64),
+}
 
-        // Count the N's
-        if let Some(n) = args.n_content {
-            if FASTQRead::count_n(seq) > n {
-                return false;
+impl QualityFilter {
+    /// Returns whether quality characters pass this filter,
+    /// or `Err` with the first character which is impossible in `encoding`.
+    pub fn passes (self, quals: &[u8], encoding: QualityEncoding, min_phred_score: usize) -> Result<bool, u8> {
+        let mut expected_errors = 0.0;
+        let mut lowest = u8::MAX;
+        let mut above = 0;
+        for &char in quals {
+            let phred = encoding.phred(char).ok_or(char)?;
+            expected_errors += error_probability(phred);
+            lowest = lowest.min(phred);
+            if phred as usize >= min_phred_score {
+                above += 1;
             }
         }
 
-        if FASTQRead::get_average_quality(quals) < args.min_phred_score {
-            return false;
+        if quals.is_empty() {
+            return Ok(min_phred_score == 0 || matches!(self, QualityFilter::MaxExpectedErrors(_)));
         }
 
-        true
+        let len = quals.len() as f64;
+        Ok(match self {
+            QualityFilter::MeanErrorProbability => error_phred(expected_errors / len) >= min_phred_score as f64,
+            QualityFilter::MinBaseQuality => lowest as usize >= min_phred_score,
+            QualityFilter::FractionAbove(fraction) => above as f64 / len >= fraction,
+            QualityFilter::MaxExpectedErrors(max) => expected_errors <= max,
+        })
     }
 }
 
-use reservoir_sampling::unweighted::l as sample;
+/// Parses the names used in JSON output, with the parameter after `=`, e.g. `fraction_above=0.9`
+impl std::str::FromStr for QualityFilter {
+    type Err = String;
 
-/** Takes in reader (for FASTQ lines) and SampleArgs,
-returns JSONified string which includes number of reads read along with base composition.
+    fn from_str (s: &str) -> Result<Self, Self::Err> {
+        let (name, param) = match s.split_once('=') {
+            Some((name, param)) => {
+                let param: f64 = param.parse().map_err(|_| format!("invalid parameter {:?} of quality filter {}", param, name))?;
+                (name, Some(param))
+            },
+            None => (s, None),
+        };
 
-Example output (example has whitespace to make it readable, output will not have that):
-```json
-{
-    lib: [
-        {
-            pos: 1,
-            bases: {
-                "A":100
-                "T":0
-                "G":0,
-                "C":0,
-                "N":0
-            }
+        match (name, param) {
+            ("mean_error_probability", None) => Ok(QualityFilter::MeanErrorProbability),
+            ("min_base_quality", None) => Ok(QualityFilter::MinBaseQuality),
+            ("fraction_above", Some(fraction)) if (0.0..=1.0).contains(&fraction) => Ok(QualityFilter::FractionAbove(fraction)),
+            ("fraction_above", _) => Err("fraction_above needs a fraction between 0 and 1, e.g. fraction_above=0.9".to_string()),
+            ("max_expected_errors", Some(max)) if max >= 0.0 => Ok(QualityFilter::MaxExpectedErrors(max)),
+            ("max_expected_errors", _) => Err("max_expected_errors needs a positive number, e.g. max_expected_errors=2".to_string()),
+            _ => Err(format!("unknown quality filter {:?}, expected mean_error_probability, min_base_quality, fraction_above=F or max_expected_errors=E", s)),
         }
-    ],
-    reads_read: 1
+    }
 }
-```
-Note: Reads read counts _number_ of reads read,
-while pos represents the _column_ of reads whose percentage is being displayed.
-*/ 
-pub fn run_json<T> (fastq_reader: FASTQReader<T>) -> String
-where T: BufRead
+
+/// Returns the probability of a base call with the given Phred score being wrong
+fn error_probability (phred: u8) -> f64 {
+    10f64.powf(-f64::from(phred) / 10.0)
+}
+
+/// Returns the (fractional) Phred score equivalent to an error probability
+fn error_phred (probability: f64) -> f64 {
+    -10.0 * probability.log10()
+}
+
+pub use crate::{Alphabet, AmbiguousBases, Rounding};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SampleArgs {
+    /// Target sample count. Set to `None` to extract base compositions of all reads, without sampling.
+    pub target_read_count: Option<u64>,
+    /// Sets minimum quality allowed in sampled reads, as judged by `quality_filter`.
+    /// Ignored for input without qualities, e.g. FASTA.
+    pub min_phred_score: usize,
+    /// Sets how qualities of reads are judged.
+    pub quality_filter: QualityFilter,
+    /// Sets maximum amount of N's allowed in sample reads. Set to none for no truncation.
+    pub n_content: Option<usize>,
+    /// Trims each sampled read to given length. Set to 0 for no trimming.
+    pub trimmed_length: usize,
+    /// Sets how malformed FASTQ records are handled.
+    pub parse_mode: ParseMode,
+    /// Seed for random sampling of reads, making sampling reproducible. Set to `None` to seed from system entropy.
+    pub seed: Option<u64>,
+    /// Minimum number of reads base compositions must be extracted from, otherwise an error is returned.
+    /// Set to `None` to use however many reads are available.
+    pub min_read_count: Option<u64>,
+    /// Sets how percentages are rounded to integers.
+    pub rounding: Rounding,
+    /// Sets how characters other than `ATGCN` in reads are counted, by default they are an error.
+    pub alphabet: Alphabet,
+    /// Reads FASTQ and FASTA input as SOLiD colorspace, converting reads to base space.
+    /// If not set, colorspace reads are an error.
+    pub colorspace: bool,
+    /// Encoding of quality scores. Set to `None` to detect it from the first records of input.
+    pub quality_encoding: Option<QualityEncoding>,
+    /// Extracts a per-position quality profile along with base compositions, for input with qualities.
+    pub quality_profile: bool,
+    /// Length of k-mers to extract a per-position k-mer composition of, along with base compositions,
+    /// from 1 to [`crate::kmer_comp::MAX_K`]. Set to `None` to not count k-mers.
+    pub kmer_length: Option<usize>,
+    /// Number of threads parsing and counting reads, 1 to count on the calling thread.
+    /// Doesn't affect results, so isn't part of the output.
+    #[serde(skip)]
+    pub threads: usize,
+}
+
+impl SampleArgs {
+    /// Returns `Err(Fastq2CompError::MismatchedSampleArgs)` naming the first field differing from `other`
+    /// which changes which reads or bases are counted, so base compositions extracted with both can't be merged.
+    fn check_merge (&self, other: &SampleArgs) -> Result<(), Fastq2CompError> {
+        let fields = [
+            ("target_read_count", self.target_read_count == other.target_read_count),
+            ("min_phred_score", self.min_phred_score == other.min_phred_score),
+            ("quality_filter", self.quality_filter == other.quality_filter),
+            ("n_content", self.n_content == other.n_content),
+            ("trimmed_length", self.trimmed_length == other.trimmed_length),
+            ("parse_mode", self.parse_mode == other.parse_mode),
+            ("min_read_count", self.min_read_count == other.min_read_count),
+            ("alphabet", self.alphabet == other.alphabet),
+            ("colorspace", self.colorspace == other.colorspace),
+            ("quality_profile", self.quality_profile == other.quality_profile),
+            ("kmer_length", self.kmer_length == other.kmer_length),
+        ];
+        match fields.iter().find(|(_, same)| !same) {
+            Some((field, _)) => Err(Fastq2CompError::MismatchedSampleArgs {field}),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Default for SampleArgs {
+    fn default() -> Self {
+        SampleArgs { target_read_count: Some(100000), min_phred_score: 0, quality_filter: QualityFilter::MeanErrorProbability, n_content: None, trimmed_length: 50, parse_mode: ParseMode::Strict, seed: None, min_read_count: None, rounding: Rounding::Truncate, alphabet: Alphabet::default(), colorspace: false, quality_encoding: None, quality_profile: false, kmer_length: None, threads: 1 }
+    }
+}
+
+use regex::bytes::Regex;
+
+use lazy_static::lazy_static;
+lazy_static! {
+    // Regex for checking if seq has numbers
+    static ref SEQCOLORSPACECHECKER: Regex = Regex::new(r"\d").unwrap();
+}
+
+/// The 4 lines making up a FASTQ record
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FASTQLine {
+    Header,
+    Seq,
+    Sep,
+    Quals,
+}
+
+/// Abstraction for a single read of FASTQ data
+#[derive(Debug)]
+pub struct FASTQRead {
+    pub(crate) seq: Vec<u8>,
+    quals: Vec<u8>,
+    header: Vec<u8>,
+    sep: Vec<u8>,
+    mode: ParseMode,
+    /// Set if reads are in colorspace, and converted to base space as they are read
+    colorspace: bool,
+    /// 1-based index of the record currently held, 0 if nothing has been read yet.
+    record: u64,
+    /// Number of malformed records skipped in lenient mode
+    records_skipped: u64,
+    /// Byte offset into input of the record currently held
+    record_offset: u64,
+    /// Bytes consumed from input so far
+    offset: u64,
+    /// Last line read, and its byte offset
+    last_line: (FASTQLine, u64),
+    /// Set if `header` already holds the header of the next record
+    pending_header: bool,
+    /// Lines read ahead while resynchronising after a malformed record, and their byte offsets,
+    /// which are read again before any more input
+    lines_ahead: VecDeque<(Vec<u8>, u64)>,
+}
+
+impl FASTQRead {
+
+    /// Reads a complete FASTQ statement (composed of 4 lines) into itself
+    /// - `reader`: Object implementing `std::io::BufRead` from which to read lines
+    /// - Returns `Ok(None)` if EOF reached.
+    /// - Returns `Err` if input could not be read, or if a malformed record is found in strict mode.
+    ///   In lenient mode, malformed records are skipped and counted instead.
+    fn read_fastq (&mut self, reader: &mut impl BufRead) -> Result<Option<()>, Fastq2CompError> {
+        loop {
+            match self.parse_record(reader) {
+                Err(Fastq2CompError::MalformedRecord {..}) if self.mode == ParseMode::Lenient => {
+                    self.records_skipped += 1;
+                    self.resync(reader)?;
+                },
+                res => return res,
+            }
+        }
+    }
+
+    /// Reads the 4 lines of a record, checking that they form a valid FASTQ record.
+    fn parse_record (&mut self, reader: &mut impl BufRead) -> Result<Option<()>, Fastq2CompError> {
+        if !self.pending_header {
+            // Skip blank lines between records
+            loop {
+                if !self.read_line(reader, FASTQLine::Header)? {
+                    return Ok(None);
+                }
+                if !self.header.is_empty() {break}
+            }
+            self.record_offset = self.last_line.1;
+        }
+        self.pending_header = false;
+        self.record += 1;
+
+        if !self.header.starts_with(b"@") {
+            return Err(self.malformed("header line does not start with '@'".to_string()));
+        }
+        for line in [FASTQLine::Seq, FASTQLine::Sep, FASTQLine::Quals].iter() {
+            if !self.read_line(reader, *line)? {
+                return Err(self.malformed("unexpected end of input".to_string()));
+            }
+            if *line == FASTQLine::Sep && !self.sep.starts_with(b"+") {
+                return Err(self.malformed("separator line does not start with '+'".to_string()));
+            }
+        }
+        if self.colorspace {
+            if let Err(c) = decode_colorspace(&mut self.seq) {
+                return Err(self.malformed(format!("invalid character {:?} in colorspace read", c as char)));
+            }
+            // Qualities may include one for the primer base
+            if self.quals.len() == self.seq.len() + 1 {
+                self.quals.remove(0);
+            }
+        }
+        if self.seq.len() != self.quals.len() {
+            return Err(self.malformed(format!(
+                "sequence length ({}) does not match quality length ({})", self.seq.len(), self.quals.len()
+            )));
+        }
+
+        Ok(Some(()))
+    }
+
+    /// Skips lines until the header of the next record, which may be the last line read.
+    /// As quality lines may start with `@` too, a line starting with `@` is only taken to be a header
+    /// if it is followed by a sequence line and a separator line starting with `+`.
+    /// Those two lines are read again as part of the record.
+    ///
+    /// Other lines starting with `@` which are skipped are counted as malformed records of their own,
+    /// unless they follow a separator line, so are qualities.
+    fn resync (&mut self, reader: &mut impl BufRead) -> io::Result<()> {
+        let (last_line, last_offset) = self.last_line;
+        let mut window = VecDeque::with_capacity(3);
+        if last_line != FASTQLine::Header {
+            window.push_back((std::mem::take(self.line_mut(last_line)), last_offset));
+        }
+        // Whether the line before the first one in the window is a separator
+        let mut after_sep = match last_line {
+            FASTQLine::Header | FASTQLine::Seq => self.header.starts_with(b"+"),
+            FASTQLine::Sep => self.seq.starts_with(b"+"),
+            FASTQLine::Quals => self.sep.starts_with(b"+"),
+        };
+
+        loop {
+            while window.len() < 3 {
+                let mut line = Vec::new();
+                match self.next_line(reader, &mut line)? {
+                    Some(offset) => window.push_back((line, offset)),
+                    None => break,
+                }
+            }
+            if window.len() == 3 && window[0].0.starts_with(b"@") && !window[1].0.starts_with(b"+") && window[2].0.starts_with(b"+") {
+                break;
+            }
+
+            // Too few lines left for a record if the window isn't full
+            let (line, _) = match window.pop_front() {
+                Some(line) => line,
+                None => return Ok(()),
+            };
+            if line.starts_with(b"@") && !after_sep {
+                self.record += 1;
+                self.records_skipped += 1;
+            }
+            after_sep = line.starts_with(b"+");
+        }
+
+        let (header, offset) = window.pop_front().unwrap();
+        self.header = header;
+        self.record_offset = offset;
+        self.pending_header = true;
+        while let Some(line) = window.pop_back() {
+            self.lines_ahead.push_front(line);
+        }
+        Ok(())
+    }
+
+    /// Reads next line of input into the buffer for `line`, stripping the line ending (`\n` or `\r\n`).
+    /// Returns `false` if EOF reached.
+    fn read_line (&mut self, reader: &mut impl BufRead, line: FASTQLine) -> io::Result<bool> {
+        let mut buf = std::mem::take(self.line_mut(line));
+        let offset = self.next_line(reader, &mut buf)?;
+        *self.line_mut(line) = buf;
+        self.last_line = (line, offset.unwrap_or(self.offset));
+        Ok(offset.is_some())
+    }
+
+    /// Reads the next line into `buf`, from `lines_ahead` if any are left there, otherwise from input.
+    /// Returns the byte offset of the line, or `None` if EOF reached.
+    fn next_line (&mut self, reader: &mut impl BufRead, buf: &mut Vec<u8>) -> io::Result<Option<u64>> {
+        if let Some((line, offset)) = self.lines_ahead.pop_front() {
+            *buf = line;
+            return Ok(Some(offset));
+        }
+
+        let offset = self.offset;
+        buf.clear();
+        let n = reader.read_until(b'\n', buf)?;
+        self.offset += n as u64;
+
+        if buf.ends_with(b"\n") {
+            buf.pop();
+            if buf.ends_with(b"\r") {
+                buf.pop();
+            }
+        }
+        Ok(if n == 0 {None} else {Some(offset)})
+    }
+
+    fn line_mut (&mut self, line: FASTQLine) -> &mut Vec<u8> {
+        match line {
+            FASTQLine::Header => &mut self.header,
+            FASTQLine::Seq => &mut self.seq,
+            FASTQLine::Sep => &mut self.sep,
+            FASTQLine::Quals => &mut self.quals,
+        }
+    }
+
+    /// Read name: the header without `@`, cut at the first whitespace, and without a trailing `/1` or `/2`
+    fn name (&self) -> &[u8] {
+        let name = self.header.get(1..).unwrap_or(&[]);
+        let name = name.split(|c| c.is_ascii_whitespace()).next().unwrap_or(name);
+        match name {
+            [rest @ .., b'/', b'1'] | [rest @ .., b'/', b'2'] => rest,
+            _ => name,
+        }
+    }
+
+    fn malformed (&self, reason: String) -> Fastq2CompError {
+        Fastq2CompError::MalformedRecord {record: self.record, offset: self.record_offset, reason}
+    }
+
+    fn new (len: usize) -> FASTQRead {
+        FASTQRead {
+            seq: Vec::with_capacity(len),
+            quals: Vec::with_capacity(len),
+            header: Vec::new(),
+            sep: Vec::new(),
+            mode: ParseMode::Strict,
+            colorspace: false,
+            record: 0,
+            records_skipped: 0,
+            record_offset: 0,
+            offset: 0,
+            last_line: (FASTQLine::Header, 0),
+            pending_header: false,
+            lines_ahead: VecDeque::new(),
+        }
+    }
+
+    /// Counts the bases in `seq` which `alphabet` counts as N
+    fn count_n(seq: &[u8], alphabet: Alphabet) -> usize {
+        seq.iter().filter(|&&c| alphabet.is_n(c)).count()
+    }
+
+    // Returns true if number is found in seq
+    fn check_colorspace(seq: &[u8]) -> bool {
+        SEQCOLORSPACECHECKER.is_match(seq)
+    }
+
+    /// Returns trimmed line.
+    /// - In case len = 0, returns line unchanged
+    /// - In case len > line len, returns Err
+    fn trim(line: &[u8], len: usize) -> Result<&[u8], ()> {
+        match len {
+            n if n != 0 => {
+                if n > line.len() {
+                    return Err(());
+                }
+                Ok(&line[0..n])
+            },
+            _ => Ok(line),
+        }
+    }
+
+}
+
+/// Formats of (decompressed) input which reads can be read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    Fastq,
+    Fasta,
+    Sam,
+    Bam,
+}
+
+impl InputFormat {
+    /// Guesses format of input from its first bytes, defaulting to FASTQ.
+    /// SAM is recognised by a header line (e.g. `@HD\t`), or by a first line of at least 11 tab separated fields.
+    pub fn detect (start: &[u8]) -> InputFormat {
+        let first_line = start.split(|&c| c == b'\n').next().unwrap_or(start);
+
+        if start.starts_with(b"BAM\x01") {
+            InputFormat::Bam
+        } else if start.starts_with(b">") {
+            InputFormat::Fasta
+        } else if matches!(first_line, [b'@', a, b, b'\t', ..] if a.is_ascii_uppercase() && b.is_ascii_uppercase())
+            || first_line.iter().filter(|&&c| c == b'\t').count() >= 10 {
+            InputFormat::Sam
+        } else {
+            InputFormat::Fastq
+        }
+    }
+}
+
+/// Source of sequencing records, parsing one record at a time from input into internal buffers.
+/// Implemented for each supported input format, so reads of any of them can be checked and sampled by [`FASTQReader`].
+/// Sources are sent to worker threads when records are parsed on them, see [`RecordSource::chunk_parser`].
+pub trait RecordSource: Send {
+    /// Reads the next record from `reader` into itself
+    /// - Returns `Ok(None)` if EOF reached.
+    /// - Returns `Err` if input could not be read, or if a malformed record is found in strict mode.
+    fn read_record<R: BufRead> (&mut self, reader: &mut R) -> Result<Option<()>, Fastq2CompError>;
+
+    /// Sequence of the record currently held
+    fn seq (&self) -> &[u8];
+
+    /// Qualities of the record currently held, `None` if the input format has no qualities.
+    fn quals (&self) -> Option<&[u8]>;
+
+    /// 1-based index of the record currently held, 0 if nothing has been read yet.
+    fn record (&self) -> u64;
+
+    /// Number of malformed records skipped so far (only in lenient parsing mode)
+    fn records_skipped (&self) -> u64;
+
+    /// Quality encoding mandated by the input format, `None` if it has to be detected.
+    fn quality_encoding (&self) -> Option<QualityEncoding> {
+        None
+    }
+
+    /// Checks the record currently held, see [`check_read`].
+    fn check_read(&mut self, args: &SampleArgs) -> Result<bool, Fastq2CompError> {
+        check_read(self.seq(), self.quals(), self.record(), args)
+    }
+
+    /// Returns a source to parse the records split off input next by [`RecordSource::split_records`],
+    /// numbering records and byte offsets on from those read or split off so far.
+    /// Returns `None` (the default) if input can't be split into records without parsing it,
+    /// in which case records are only parsed by this source.
+    fn chunk_parser (&self) -> Option<Self> where Self: Sized {
+        None
+    }
+
+    /// Moves the raw input of up to `records` records from `reader` to the end of `chunk`, without parsing them,
+    /// so that they can be parsed on another thread by a source returned by [`RecordSource::chunk_parser`].
+    /// Returns the number of records split off, fewer than `records` only at the end of input.
+    /// Only called if `chunk_parser` returns a source.
+    fn split_records<R: BufRead> (&mut self, _reader: &mut R, _chunk: &mut Vec<u8>, _records: usize) -> io::Result<usize> {
+        Ok(0)
+    }
+}
+
+/// Converts a SOLiD colorspace read, a primer base followed by color calls `0123`, to base space in place.
+/// The primer base is the last base of the adapter, so isn't part of the converted read.
+/// As each base is decoded from the one before it, a missing color call (`.`) makes its base and all following ones `N`.
+/// Returns `Err` with the first character which doesn't belong in a colorspace read.
+fn decode_colorspace (seq: &mut Vec<u8>) -> Result<(), u8> {
+    // Color calls are the XOR of the 2-bit codes of adjacent bases
+    const BASES: &[u8; 4] = b"ACGT";
+
+    let primer = match seq.first() {
+        Some(&primer) => primer,
+        None => return Ok(()),
+    };
+    let mut prev = Some(BASES.iter().position(|&base| base == primer.to_ascii_uppercase()).ok_or(primer)?);
+    for i in 1..seq.len() {
+        prev = match (prev, seq[i]) {
+            (Some(base), color @ b'0'..=b'3') => Some(base ^ (color - b'0') as usize),
+            (None, b'0'..=b'3') | (_, b'.') => None,
+            (_, c) => return Err(c),
+        };
+        seq[i - 1] = prev.map_or(b'N', |base| BASES[base]);
+    }
+    seq.pop();
+
+    Ok(())
+}
+
+/** Checks read according to parameters given in SampleArgs,
+return `true` if read should be included in calculation of Base Compositions,
+return `false` if not.
+
+Eg.
+Read "N" and SampleArgs.n_content: Some(1) will return false.
+
+Reads without qualities are not filtered on quality.
+Qualities are decoded using `args.quality_encoding`, Phred+33 if it is `None`.
+Returns `Err` if the read contains numbers, as this is probably colorspace data which wasn't converted (see `SampleArgs::colorspace`),
+if it has characters which the alphabet rejects, or if it has quality characters which are impossible in the quality encoding.
+*/
+fn check_read(seq: &[u8], quals: Option<&[u8]>, record: u64, args: &SampleArgs) -> Result<bool, Fastq2CompError> {
+    let seq = match FASTQRead::trim(seq, args.trimmed_length) {
+        Ok(s) => s,
+        _ => return Ok(false),
+    };
+    let quals = match quals.map(|q| FASTQRead::trim(q, args.trimmed_length)) {
+        Some(Ok(q)) => Some(q),
+        Some(Err(())) => return Ok(false),
+        None => None,
+    };
+
+    // Check for numbers in reads
+    if FASTQRead::check_colorspace(seq) {
+        return Err(Fastq2CompError::Colorspace {record});
+    }
+
+    if let Some((i, base)) = args.alphabet.find_rejected(seq) {
+        return Err(Fastq2CompError::InvalidBase {base: base as char, pos: i + 1, record});
+    }
+
+    // Count the N's
+    if let Some(n) = args.n_content {
+        if FASTQRead::count_n(seq, args.alphabet) > n {
+            return Ok(false);
+        }
+    }
+
+    if let Some(quals) = quals {
+        let encoding = args.quality_encoding.unwrap_or(QualityEncoding::Sanger);
+        match args.quality_filter.passes(quals, encoding, args.min_phred_score) {
+            Ok(false) => return Ok(false),
+            Ok(true) => {},
+            Err(qual) => return Err(Fastq2CompError::InvalidQuality {qual: qual as char, record}),
+        }
+    }
+
+    Ok(true)
+}
+
+/// Decodes the qualities of a read which passed `check_read` into Phred scores, trimmed as the read is.
+/// Clears `phreds` first, leaving it empty if the read has no qualities.
+fn decode_phreds (quals: Option<&[u8]>, args: &SampleArgs, phreds: &mut Vec<u8>) {
+    phreds.clear();
+    if let Some(quals) = quals {
+        let encoding = args.quality_encoding.unwrap_or(QualityEncoding::Sanger);
+        // Qualities were validated by check_read
+        let quals = FASTQRead::trim(quals, args.trimmed_length).unwrap();
+        phreds.extend(quals.iter().map(|&qual| encoding.phred(qual).unwrap_or(0)));
+    }
+}
+
+/// Checks a read with `check`, falling back to Phred+33 if its qualities are impossible in an encoding +64 detected from
+/// the records read ahead (`detected` set), but possible in Phred+33, as detection can be misled by records of high quality.
+/// Reads already checked are kept, so a warning is printed.
+fn check_detected (args: &mut SampleArgs, detected: bool, mut check: impl FnMut(&SampleArgs) -> Result<bool, Fastq2CompError>)
+    -> Result<bool, Fastq2CompError>
+{
+    match check(args) {
+        Err(Fastq2CompError::InvalidQuality {qual, record})
+            if detected && args.quality_encoding != Some(QualityEncoding::Sanger) && QualityEncoding::Sanger.phred(qual as u8).is_some() =>
+        {
+            eprintln!(
+                "Warning: quality {:?} of record #{} is impossible in the detected quality encoding, reading the rest of input as sanger (Phred+33)",
+                qual, record
+            );
+            args.quality_encoding = Some(QualityEncoding::Sanger);
+            check(args)
+        },
+        res => res,
+    }
+}
+
+/// Copy of a record, read ahead while detecting the quality encoding of input
+#[derive(Debug, Default)]
+struct Lookahead {
+    seq: Vec<u8>,
+    quals: Option<Vec<u8>>,
+    record: u64,
+}
+
+impl Lookahead {
+    fn new (source: &impl RecordSource) -> Lookahead {
+        Lookahead {seq: source.seq().to_vec(), quals: source.quals().map(<[u8]>::to_vec), record: source.record()}
+    }
+
+    fn check_read (&self, args: &SampleArgs) -> Result<bool, Fastq2CompError> {
+        check_read(&self.seq, self.quals.as_deref(), self.record, args)
+    }
+}
+
+/// Number of records read ahead to detect the quality encoding of input
+const QUALITY_DETECTION_RECORDS: usize = 1000;
+
+/// Lowest and highest quality characters seen, for detecting quality encoding
+#[derive(Debug, Default)]
+struct QualityRange(Option<(u8, u8)>);
+
+impl QualityRange {
+    fn update (&mut self, quals: Option<&[u8]>) {
+        for &qual in quals.unwrap_or(&[]) {
+            let (lowest, highest) = self.0.get_or_insert((qual, qual));
+            *lowest = qual.min(*lowest);
+            *highest = qual.max(*highest);
+        }
+    }
+
+    /// Detected encoding, Phred+33 if no qualities were seen
+    fn encoding (&self) -> QualityEncoding {
+        match self.0 {
+            Some((lowest, highest)) => QualityEncoding::detect(lowest, highest),
+            None => QualityEncoding::Sanger,
+        }
+    }
+}
+
+impl RecordSource for FASTQRead {
+    fn read_record<R: BufRead> (&mut self, reader: &mut R) -> Result<Option<()>, Fastq2CompError> {
+        self.read_fastq(reader)
+    }
+
+    fn seq (&self) -> &[u8] {
+        &self.seq
+    }
+
+    fn quals (&self) -> Option<&[u8]> {
+        Some(&self.quals)
+    }
+
+    fn record (&self) -> u64 {
+        self.record
+    }
+
+    fn records_skipped (&self) -> u64 {
+        self.records_skipped
+    }
+
+    /// Records are only split off in strict mode, as resynchronising after a malformed record needs parsing.
+    fn chunk_parser (&self) -> Option<Self> {
+        if self.mode != ParseMode::Strict || self.pending_header {
+            return None;
+        }
+        let mut parser = FASTQRead::new(self.seq.capacity());
+        parser.colorspace = self.colorspace;
+        parser.record = self.record;
+        parser.offset = self.offset;
+        Some(parser)
+    }
+
+    /// Splits lines into records as [`FASTQRead::parse_record`] does: blank lines, then a header and 3 more lines.
+    fn split_records<R: BufRead> (&mut self, reader: &mut R, chunk: &mut Vec<u8>, records: usize) -> io::Result<usize> {
+        for split in 0..records {
+            loop {
+                let start = chunk.len();
+                let n = reader.read_until(b'\n', chunk)?;
+                self.offset += n as u64;
+                if n == 0 {
+                    return Ok(split);
+                }
+                if !matches!(&chunk[start..], b"\n" | b"\r\n") {break}
+            }
+            for _ in 0..3 {
+                let n = reader.read_until(b'\n', chunk)?;
+                self.offset += n as u64;
+                if n == 0 {break}
+            }
+            self.record += 1;
+        }
+        Ok(records)
+    }
+}
+
+/// Abstraction for a single record of FASTA data, whose sequence may be wrapped over several lines.
+/// FASTA has no qualities, so reads are not filtered on quality.
+#[derive(Debug)]
+pub struct FASTARead {
+    seq: Vec<u8>,
+    /// Last line read
+    line: Vec<u8>,
+    mode: ParseMode,
+    /// Set if reads are in colorspace, and converted to base space as they are read
+    colorspace: bool,
+    /// 1-based index of the record currently held, 0 if nothing has been read yet.
+    record: u64,
+    /// Byte offset into input of the header of the record currently held
+    record_offset: u64,
+    /// Number of malformed records skipped in lenient mode
+    records_skipped: u64,
+    /// Byte offset into input of the last line read
+    line_offset: u64,
+    /// Bytes consumed from input so far
+    offset: u64,
+    /// Set if `line` already holds the header of the next record
+    pending_header: bool,
+}
+
+impl FASTARead {
+    fn new (len: usize) -> FASTARead {
+        FASTARead {
+            seq: Vec::with_capacity(len),
+            line: Vec::new(),
+            mode: ParseMode::Strict,
+            colorspace: false,
+            record: 0,
+            record_offset: 0,
+            records_skipped: 0,
+            line_offset: 0,
+            offset: 0,
+            pending_header: false,
+        }
+    }
+
+    /// Reads a complete FASTA record (a header line starting with `>`, and all sequence lines up to the next header) into itself.
+    /// Blank lines and comment lines starting with `;` are skipped.
+    /// - Returns `Ok(None)` if EOF reached.
+    /// - Returns `Err` if input could not be read, or if lines are found before the first header in strict mode.
+    ///   In lenient mode, such lines are skipped and counted as a malformed record instead.
+    fn read_fasta (&mut self, reader: &mut impl BufRead) -> Result<Option<()>, Fastq2CompError> {
+        while !self.pending_header {
+            if !self.read_line(reader)? {
+                return Ok(None);
+            }
+            // Skip blank and comment lines between records
+            if self.line.is_empty() || self.line.starts_with(b";") {continue}
+
+            if self.line.starts_with(b">") {break}
+            if self.mode == ParseMode::Strict {
+                return Err(Fastq2CompError::MalformedRecord {
+                    record: self.record + 1,
+                    offset: self.line_offset,
+                    reason: "header line does not start with '>'".to_string(),
+                });
+            }
+
+            // Skip lines up to the next header
+            self.records_skipped += 1;
+            loop {
+                if !self.read_line(reader)? {
+                    return Ok(None);
+                }
+                if self.line.starts_with(b">") {break}
+            }
+            break;
+        }
+        self.pending_header = false;
+        self.record += 1;
+        self.record_offset = self.line_offset;
+
+        self.seq.clear();
+        while self.read_line(reader)? {
+            if self.line.starts_with(b">") {
+                self.pending_header = true;
+                break;
+            }
+            if self.line.starts_with(b";") {continue}
+            self.seq.extend(self.line.iter().filter(|c| !c.is_ascii_whitespace()));
+        }
+
+        if self.colorspace {
+            if let Err(c) = decode_colorspace(&mut self.seq) {
+                return Err(Fastq2CompError::MalformedRecord {
+                    record: self.record,
+                    offset: self.record_offset,
+                    reason: format!("invalid character {:?} in colorspace read", c as char),
+                });
+            }
+        }
+
+        Ok(Some(()))
+    }
+
+    /// Reads next line of input, stripping the line ending (`\n` or `\r\n`).
+    /// Returns `false` if EOF reached.
+    fn read_line (&mut self, reader: &mut impl BufRead) -> io::Result<bool> {
+        self.line_offset = self.offset;
+        self.line.clear();
+        let n = reader.read_until(b'\n', &mut self.line)?;
+        self.offset += n as u64;
+
+        if self.line.ends_with(b"\n") {
+            self.line.pop();
+            if self.line.ends_with(b"\r") {
+                self.line.pop();
+            }
+        }
+        Ok(n != 0)
+    }
+}
+
+impl RecordSource for FASTARead {
+    fn read_record<R: BufRead> (&mut self, reader: &mut R) -> Result<Option<()>, Fastq2CompError> {
+        self.read_fasta(reader)
+    }
+
+    fn seq (&self) -> &[u8] {
+        &self.seq
+    }
+
+    fn quals (&self) -> Option<&[u8]> {
+        None
+    }
+
+    fn quality_encoding (&self) -> Option<QualityEncoding> {
+        // Nothing to detect
+        Some(QualityEncoding::Sanger)
+    }
+
+    fn record (&self) -> u64 {
+        self.record
+    }
+
+    fn records_skipped (&self) -> u64 {
+        self.records_skipped
+    }
+}
+
+use std::collections::VecDeque;
+use std::sync::{mpsc, Arc, Mutex};
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg64;
+
+/** Takes in reader (for FASTQ lines) and SampleArgs,
+returns JSONified [`Output`], which includes number of reads read along with base composition.
+*/
+pub fn run_json<T, S> (fastq_reader: FASTQReader<T, S>) -> Result<String, Fastq2CompError>
+where T: BufRead, S: RecordSource
 {
-    let comp = run (fastq_reader);
+    let sample_args = fastq_reader.sample_args;
+    let comp = run (fastq_reader)?;
 
-    serde_json::to_string(&comp).expect("Error converting base compositions to JSON")
+    Output::new(comp, sample_args).to_json()
 }
 
 /**
@@ -391,90 +2032,1107 @@ Output for 1 read with two columns.
 )
 ```
 */
-pub fn run_tsv<T> (fastq_reader: FASTQReader<T>) -> (String, u64)
-where T: BufRead
+pub fn run_tsv<T, S> (fastq_reader: FASTQReader<T, S>) -> Result<(String, u64), Fastq2CompError>
+where T: BufRead, S: RecordSource
+{
+    run_table(fastq_reader, &TableOptions::default())
+}
+
+/// Layout of tabular output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TableLayout {
+    /// Single row holding the percentages of all columns, in `A C G T N` order per column.
+    /// Header is `A1 C1 G1 T1 N1 A2 ...`
+    Wide,
+    /// One row per base per column (tidy data, as used by R and pandas).
+    /// Header is `pos base count percent`
+    Long,
+}
+
+/// Options for tabular output of base compositions
+#[derive(Debug, Clone, Copy)]
+pub struct TableOptions {
+    pub layout: TableLayout,
+    /// Whether to output a header row
+    pub header: bool,
+    /// Separator between fields, e.g. `\t` for TSV or `,` for CSV.
+    pub delimiter: char,
+}
+
+impl Default for TableOptions {
+    fn default() -> Self {
+        TableOptions { layout: TableLayout::Wide, header: false, delimiter: '\t' }
+    }
+}
+
+/// Takes in reader (for FASTQ lines) and SampleArgs,
+/// returns tuple of base compositions formatted as table (see [`format_table`]) and number of reads read.
+pub fn run_table<T, S> (fastq_reader: FASTQReader<T, S>, options: &TableOptions) -> Result<(String, u64), Fastq2CompError>
+where T: BufRead, S: RecordSource
 {
-    let comp = run (fastq_reader);
-    let lines_read = comp.reads_read;
+    let comp = run (fastq_reader)?;
 
-    ({let mut s = comp.lib.into_iter().flat_map(|b| b.bases.iter()).
-        fold(String::new(), |acc, curr| acc + &curr.to_string() + "\t");
-        s.pop(); // remove trailing ',' to make it valid tsv
-        s
+    Ok((format_table(&comp, options), comp.reads_read))
+}
+
+/// Formats base compositions as table, with rows separated by newlines (and no trailing newline).
+/// The header, if any, is derived from the number of columns in `comp`. Percentages of columns without bases are left empty.
+pub fn format_table (comp: &BaseComp, options: &TableOptions) -> String {
+    const BASES: [char; 5] = ['A', 'C', 'G', 'T', 'N'];
+    let delimiter = options.delimiter.to_string();
+    let mut rows = Vec::new();
+
+    match options.layout {
+        TableLayout::Wide => {
+            if options.header {
+                rows.push(comp.lib.iter()
+                    .flat_map(|col| BASES.iter().map(move |base| format!("{}{}", base, col.pos)))
+                    .collect::<Vec<_>>().join(&delimiter));
+            }
+            rows.push(comp.lib.iter()
+                .flat_map(percent_fields)
+                .collect::<Vec<_>>().join(&delimiter));
+        },
+        TableLayout::Long => {
+            if options.header {
+                rows.push(["pos", "base", "count", "percent"].join(&delimiter));
+            }
+            for col in comp.lib.iter() {
+                for ((base, count), percent) in BASES.iter().zip(col.bases.iter()).zip(percent_fields(col)) {
+                    rows.push([col.pos.to_string(), base.to_string(), count.to_string(), percent].join(&delimiter));
+                }
+            }
+        },
+    }
+
+    rows.join("\n")
+}
+
+/// Percentages of a column formatted as table fields, in `A C G T N` order, empty if the column has no bases
+fn percent_fields (col: &BaseCompCol) -> Vec<String> {
+    match col.percentages {
+        Some(percentages) => percentages.iter().map(|p| p.to_string()).collect(),
+        None => vec![String::new(); 5],
+    }
+}
+
+/// Version of the JSON output format, incremented whenever it changes incompatibly.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/** Envelope in which base compositions are output as JSON,
+recording how and from what they were extracted.
+
+Example output (example has whitespace to make it readable, output will not have that):
+```json
+{
+    "schema_version": 1,
+    "tool_version": "0.5.0",
+    "inputs": ["in.fastq.gz"],
+    "sample_args": {
+        "target_read_count": 100000,
+        "min_phred_score": 0,
+        "quality_filter": "mean_error_probability",
+        "n_content": null,
+        "trimmed_length": 2,
+        "parse_mode": "strict",
+        "seed": null,
+        "min_read_count": null,
+        "rounding": "truncate",
+        "alphabet": {"fold_lowercase": false, "u_as_t": false, "ambiguous": "reject"},
+        "colorspace": false,
+        "quality_encoding": "sanger",
+        "quality_profile": false,
+        "kmer_length": null
     },
-    lines_read)
+    "comp": {
+        "lib": [
+            {
+                "pos": 1,
+                "coverage": 1,
+                "bases": {"A": 1, "T": 0, "G": 0, "C": 0, "N": 0},
+                "percentages": {"A": 100, "T": 0, "G": 0, "C": 0, "N": 0},
+                "fractions": {"A": 1.0, "T": 0.0, "G": 0.0, "C": 0.0, "N": 0.0}
+            },
+            ...
+        ],
+        "gc_content": {
+            "counts": [1, 0, 0, ...],
+            "fit": null
+        },
+        "reads_read": 1,
+        "reads_accepted": 1
+    }
+}
+```
+Note: Reads read counts _number_ of reads read,
+while pos represents the _column_ of reads whose percentage is being displayed.
+Reads accepted counts all reads in the input which passed the checks, of which reads read were sampled.
+`gc_content` holds the number of reads read of each GC percentage from 0 to 100, and a normal distribution fitted to them
+(`null` if it couldn't be fitted, as here), see [`crate::GcContent`].
+`inputs` holds `-` for stdin, and is empty if unknown.
+`quality_encoding` is the encoding qualities were read in, as detected from the input if not given (`sanger` for input without qualities).
+If the alphabet counts ambiguity codes separately, columns holding any also have an `other` count.
+
+If `quality_profile` is set, each column also holds the qualities of its bases as `quals`:
+```json
+"quals": {
+    "counts": [0, 0, 1, 0, ...],
+    "bases": {"A": 1, "T": 0, "G": 0, "C": 0, "N": 0},
+    "sums": {"A": 2, "T": 0, "G": 0, "C": 0, "N": 0},
+    "mean": 2.0,
+    "lower_quartile": 2,
+    "median": 2,
+    "upper_quartile": 2,
+    "base_means": {"A": 2.0, "T": null, "G": null, "C": null, "N": null}
 }
+```
+`counts` holds the number of bases with each Phred score, indexed by score.
+Columns without any qualities (e.g. from FASTA input) have no `quals`.
 
-use serde::{Deserialize, Serialize};
+If `kmer_length` is set, `comp` also holds the k-mer composition of the reads as `kmers`, see [`crate::kmer_comp::KmerComp`]:
+```json
+"kmers": {
+    "k": 2,
+    "lib": [
+        {
+            "pos": 1,
+            "coverage": 1,
+            "counts": {"AC": 1},
+            "percentages": {"AC": 100},
+            "fractions": {"AC": 1.0}
+        },
+        ...
+    ],
+    "reads_read": 1,
+    "alphabet": {"fold_lowercase": false, "u_as_t": false, "ambiguous": "reject"}
+}
+```
+k-mers are keyed by their bases, and k-mers with a value of 0 are left out.
+Columns without any k-mers (e.g. the last `k - 1` of the longest reads) have `null` percentages and fractions.
+
+For paired-end input, `comp` holds the base compositions of read 1, and `comp_r2` those of read 2.
+`comp_r2` is left out for single-end input.
+*/
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Output {
-    processed_num: u64,
-    out: BaseComp,
+    pub schema_version: u32,
+    pub tool_version: String,
+    pub inputs: Vec<String>,
+    pub sample_args: SampleArgs,
+    pub comp: BaseComp,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comp_r2: Option<BaseComp>,
+}
+
+impl Output {
+    /// Records the quality encoding detected while extracting `comp`, if `sample_args` doesn't give one.
+    pub fn new (comp: BaseComp, sample_args: SampleArgs) -> Output {
+        let sample_args = SampleArgs {quality_encoding: sample_args.quality_encoding.or(comp.quality_encoding()), ..sample_args};
+        Output {
+            schema_version: SCHEMA_VERSION,
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            inputs: Vec::new(),
+            sample_args,
+            comp,
+            comp_r2: None,
+        }
+    }
+
+    /// Output of paired-end base compositions, of read 1 and read 2 respectively.
+    /// The `parse_mode` is recorded as strict, as [`PairedFASTQReader`] is never lenient.
+    pub fn paired (r1_comp: BaseComp, r2_comp: BaseComp, sample_args: SampleArgs) -> Output {
+        Output {
+            comp_r2: Some(r2_comp),
+            ..Output::new(r1_comp, SampleArgs {parse_mode: ParseMode::Strict, ..sample_args})
+        }
+    }
+
+    /// Merges the base compositions of `other` into these, appending its inputs.
+    /// Returns `Err(Fastq2CompError::MixedPairing)` if only one of them is paired-end,
+    /// `Err(Fastq2CompError::MismatchedKmerLength)` if both have k-mer compositions of differing k-mer lengths,
+    /// and `Err(Fastq2CompError::MismatchedSampleArgs)` if they were extracted with `SampleArgs` counting different reads or bases.
+    /// The seed and quality encoding are kept if both agree on them, and cleared otherwise.
+    pub fn merge (&mut self, other: Output) -> Result<(), Fastq2CompError> {
+        // Checked up front, so that nothing is merged if either composition can't be
+        match (&self.comp_r2, &other.comp_r2) {
+            (Some(comp_r2), Some(other_r2)) => comp_r2.check_merge(other_r2)?,
+            (None, None) => {},
+            _ => return Err(Fastq2CompError::MixedPairing),
+        }
+        self.comp.check_merge(&other.comp)?;
+        self.sample_args.check_merge(&other.sample_args)?;
+
+        if self.sample_args.seed != other.sample_args.seed {
+            self.sample_args.seed = None;
+        }
+        if self.sample_args.quality_encoding != other.sample_args.quality_encoding {
+            self.sample_args.quality_encoding = None;
+        }
+
+        if let (Some(comp_r2), Some(other_r2)) = (&mut self.comp_r2, &other.comp_r2) {
+            comp_r2.merge(other_r2)?;
+        }
+        self.comp.merge(&other.comp)?;
+        self.inputs.extend(other.inputs);
+        Ok(())
+    }
+
+    /// Computes percentages of all base compositions held
+    pub fn compute_percentages (&mut self, rounding: Rounding) {
+        self.comp.compute_percentages(rounding);
+        if let Some(comp_r2) = &mut self.comp_r2 {
+            comp_r2.compute_percentages(rounding);
+        }
+    }
+
+    pub fn to_json (&self) -> Result<String, Fastq2CompError> {
+        Ok(serde_json::to_string(self)?)
+    }
+
+    /// Parses JSON output, returning `Err(Fastq2CompError::IncompatibleSchema)`
+    /// if it was written using a different schema version (or before versioning).
+    pub fn from_json (json: &str) -> Result<Output, Fastq2CompError> {
+        #[derive(Deserialize)]
+        struct Version {
+            schema_version: Option<u32>,
+        }
+
+        let version = serde_json::from_str::<Version>(json)?.schema_version;
+        if version != Some(SCHEMA_VERSION) {
+            return Err(Fastq2CompError::IncompatibleSchema {found: version, supported: SCHEMA_VERSION});
+        }
+
+        Ok(serde_json::from_str(json)?)
+    }
 }
 
 /// Takes in reader (for FASTQ lines) and SampleArgs, returns [`BaseComp`]
-pub fn run<T> (fastq_reader: FASTQReader<T>) -> BaseComp
+/// - If `target_read_count` is `None`, every read passing the checks in `SampleArgs` is used,
+///   otherwise the reads are randomly sampled first.
+/// - If fewer reads than `target_read_count` pass the checks, all of them are used.
+/// - Returns `Err(Fastq2CompError::EmptyInput)` if no reads could be sampled,
+///   and `Err(Fastq2CompError::TooFewReads)` if fewer than `min_read_count` could be.
+pub fn run<T, S> (mut fastq_reader: FASTQReader<T, S>) -> Result<BaseComp, Fastq2CompError>
+where T: BufRead, S: RecordSource
+{
+    check_kmer_length(&fastq_reader.sample_args)?;
+    let threads = fastq_reader.sample_args.threads;
+    let base_comp = match fastq_reader.target_read_count {
+        Some(target_read_count) => extract_sampled(&mut fastq_reader, target_read_count)?,
+        None if threads > 1 => extract_all_threaded(&mut fastq_reader, threads)?,
+        None => extract_all(&mut fastq_reader)?,
+    };
+
+    finish(base_comp, &fastq_reader.sample_args, fastq_reader.reads_accepted(), fastq_reader.records_skipped())
+}
+
+/// Takes in reader for paired-end FASTQ data and SampleArgs,
+/// returns [`BaseComp`] of read 1 and of read 2, extracted from the same pairs as [`run`] would for single reads.
+/// Reads read and accepted are counted in pairs.
+pub fn run_paired<T> (mut fastq_reader: PairedFASTQReader<T>) -> Result<(BaseComp, BaseComp), Fastq2CompError>
 where T: BufRead
 {
-    //TODO: Convert args.target_read_count to usize or figure out how to allocate u64-sized vec
-    let sampled_seqs = fastq_reader.sample_random();
+    let args = fastq_reader.sample_args;
+    check_kmer_length(&args)?;
+    let mut r1_comp = new_base_comp(&args);
+    let mut r2_comp = new_base_comp(&args);
+
+    match (fastq_reader.target_read_count, args.quality_profile) {
+        (Some(_), false) => for (r1, r2) in fastq_reader.sample_random()? {
+            r1_comp.extract(&r1)?;
+            r2_comp.extract(&r2)?;
+        },
+        (Some(target_read_count), true) => {
+            let mut reservoir = Reservoir::<(ReadPair, ReadPair)>::new(target_read_count, args.seed);
+            let mut phreds: ReadPair = Default::default();
+            while let Some((r1, r2)) = fastq_reader.next_pair_with_phreds(&mut phreds)? {
+                if let Some(((r1_slot, r2_slot), (r1_phreds, r2_phreds))) = reservoir.offer() {
+                    copy_into(r1_slot, r1);
+                    copy_into(r2_slot, r2);
+                    copy_into(r1_phreds, &phreds.0);
+                    copy_into(r2_phreds, &phreds.1);
+                }
+            }
+            for ((r1, r2), (r1_phreds, r2_phreds)) in reservoir.into_sampled() {
+                r1_comp.extract_with_phreds(&r1, &r1_phreds)?;
+                r2_comp.extract_with_phreds(&r2, &r2_phreds)?;
+            }
+        },
+        (None, false) => while let Some((r1, r2)) = fastq_reader.next_pair()? {
+            r1_comp.extract(r1)?;
+            r2_comp.extract(r2)?;
+        },
+        (None, true) => {
+            let mut phreds = Default::default();
+            while let Some((r1, r2)) = fastq_reader.next_pair_with_phreds(&mut phreds)? {
+                r1_comp.extract_with_phreds(r1, &phreds.0)?;
+                r2_comp.extract_with_phreds(r2, &phreds.1)?;
+            }
+        },
+    }
 
-    // Figure out allotment size based on line size, or provided trim len
-    let mut base_comp = BaseComp::init(sampled_seqs[0].len());
+    // Sample arguments of the reader hold the quality encoding if it was detected
+    let (args, reads_accepted) = (fastq_reader.sample_args, fastq_reader.reads_accepted());
+    Ok((finish(r1_comp, &args, reads_accepted, 0)?, finish(r2_comp, &args, reads_accepted, 0)?))
+}
 
-    for seq in sampled_seqs {
-        if seq.is_empty() {
-            break;
+/// Returns `Err(Fastq2CompError::InvalidKmerLength)` if k-mers of the length in `args` can't be counted.
+fn check_kmer_length (args: &SampleArgs) -> Result<(), Fastq2CompError> {
+    match args.kmer_length {
+        Some(k) => KmerComp::check_k(k),
+        None => Ok(()),
+    }
+}
+
+/// Empty base composition to extract reads into as `args` say, counting k-mers if enabled.
+fn new_base_comp (args: &SampleArgs) -> BaseComp {
+    let mut base_comp = BaseComp::with_alphabet(0, args.alphabet);
+    if let Some(k) = args.kmer_length {
+        base_comp.count_kmers(k);
+    }
+    base_comp
+}
+
+/// Computes percentages of extracted base compositions and records the read counts,
+/// checking that enough reads were extracted.
+fn finish (mut base_comp: BaseComp, args: &SampleArgs, reads_accepted: u64, records_skipped: u64) -> Result<BaseComp, Fastq2CompError> {
+    // Accepted reads may all be empty, leaving no columns
+    if reads_accepted == 0 {
+        return Err(Fastq2CompError::EmptyInput);
+    }
+
+    base_comp.compute_percentages(args.rounding);
+    base_comp.records_skipped = records_skipped;
+    base_comp.reads_accepted = reads_accepted;
+    base_comp.quality_encoding = args.quality_encoding;
+
+    match args.min_read_count {
+        Some(min) if base_comp.reads_read < min => Err(Fastq2CompError::TooFewReads {reads: base_comp.reads_read, min}),
+        _ => Ok(base_comp),
+    }
+}
+
+/// Owned sequence of a sampled read and its Phred scores, empty unless the quality profile is enabled
+type SampledRead = (Vec<u8>, Vec<u8>);
+
+/// Randomly samples reads, then extracts their base compositions.
+/// Phred scores of the reads are sampled along with them if the quality profile is enabled.
+/// Only reads which are sampled are copied out of the reader.
+fn extract_sampled<T: BufRead, S: RecordSource> (fastq_reader: &mut FASTQReader<T, S>, target_read_count: u64) -> Result<BaseComp, Fastq2CompError> {
+    let args = fastq_reader.sample_args;
+
+    let mut reservoir = Reservoir::<SampledRead>::new(target_read_count, args.seed);
+    let mut phreds = Vec::new();
+    loop {
+        let read = match args.quality_profile {
+            true => fastq_reader.next_read_with_phreds(&mut phreds)?,
+            false => fastq_reader.next_read()?,
+        };
+        let seq = match read {
+            Some(seq) => seq,
+            None => break,
+        };
+        if let Some((seq_slot, phreds_slot)) = reservoir.offer() {
+            copy_into(seq_slot, seq);
+            copy_into(phreds_slot, &phreds);
         }
-        base_comp.extract(&seq);
     }
+    let sampled_reads = reservoir.into_sampled();
+
+    let threads = args.threads.max(1);
+    let per_thread = sampled_reads.len().div_ceil(threads).max(1);
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = sampled_reads.chunks(per_thread).map(|reads| scope.spawn(move || -> Result<BaseComp, Fastq2CompError> {
+            let mut base_comp = new_base_comp(&args);
+            for (seq, phreds) in reads {
+                base_comp.extract_with_phreds(seq, phreds)?;
+            }
+            Ok(base_comp)
+        })).collect();
+
+        let mut base_comp = BaseComp::init(0);
+        for worker in workers {
+            base_comp.merge(&worker.join().unwrap()?)?;
+        }
+        Ok(base_comp)
+    })
+}
 
-    for r in base_comp.lib.iter_mut() {
-        r.bases.percentage();
+/// Extracts base compositions of every read as it is read,
+/// so only a single read is held in memory at a time.
+fn extract_all<T: BufRead, S: RecordSource> (fastq_reader: &mut FASTQReader<T, S>) -> Result<BaseComp, Fastq2CompError> {
+    let mut base_comp = new_base_comp(&fastq_reader.sample_args);
+
+    if fastq_reader.sample_args.quality_profile {
+        let mut phreds = Vec::new();
+        while let Some(seq) = fastq_reader.next_read_with_phreds(&mut phreds)? {
+            base_comp.extract_with_phreds(seq, &phreds)?;
+        }
+    } else {
+        while let Some(seq) = fastq_reader.next_read()? {
+            base_comp.extract(seq)?;
+        }
     }
 
-    base_comp
+    Ok(base_comp)
 }
 
+/// Extracts base compositions of every read on `threads` worker threads, each counting bases into its own [`BaseComp`].
+/// The compositions of the workers are merged at the end.
+///
+/// If the source can split its input into records without parsing them (see [`RecordSource::chunk_parser`]),
+/// the calling thread only does that, and records are parsed and checked on the workers too.
+/// Otherwise records are parsed and checked on the calling thread, and passed to the workers in batches.
+fn extract_all_threaded<T: BufRead, S: RecordSource> (fastq_reader: &mut FASTQReader<T, S>, threads: usize) -> Result<BaseComp, Fastq2CompError> {
+    // Reads read ahead while detecting the quality encoding are parsed already
+    let mut base_comp = new_base_comp(&fastq_reader.sample_args);
+    let quality_profile = fastq_reader.sample_args.quality_profile;
+    let mut phreds = Vec::new();
+    while let Some(seq) = fastq_reader.next_lookahead_read(if quality_profile {Some(&mut phreds)} else {None})? {
+        base_comp.extract_with_phreds(seq, &phreds)?;
+    }
+
+    // Falling back from a detected encoding +64 (see `check_detected`) needs records to be checked in order
+    let may_fall_back = fastq_reader.encoding_detected && fastq_reader.sample_args.quality_encoding != Some(QualityEncoding::Sanger);
+    let comp = match fastq_reader.curr.chunk_parser() {
+        Some(_) if !may_fall_back => extract_chunks_threaded(fastq_reader, threads)?,
+        _ => extract_batches_threaded(fastq_reader, threads)?,
+    };
+    base_comp.merge(&comp)?;
+    Ok(base_comp)
+}
+
+/// Splits input into chunks of whole records on the calling thread, which `threads` workers parse and extract reads from.
+fn extract_chunks_threaded<T: BufRead, S: RecordSource> (fastq_reader: &mut FASTQReader<T, S>, threads: usize) -> Result<BaseComp, Fastq2CompError> {
+    /// Records per chunk sent to a worker
+    const CHUNK_RECORDS: usize = 4096;
+
+    std::thread::scope(|scope| {
+        let (sender, receiver) = mpsc::sync_channel::<(usize, S, Vec<u8>)>(2 * threads);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let args = fastq_reader.sample_args;
+
+        // Workers stop at the first error in their chunks, returned along with the index of its chunk
+        let workers: Vec<_> = (0..threads).map(|_| {
+            let receiver = Arc::clone(&receiver);
+            scope.spawn(move || -> Result<(BaseComp, u64), (usize, Fastq2CompError)> {
+                let (mut base_comp, mut reads_accepted) = (new_base_comp(&args), 0);
+                let mut phreds = Vec::new();
+                // Lock is only held while waiting for the next chunk
+                while let Ok((index, parser, chunk)) = {let chunk = receiver.lock().unwrap().recv(); chunk} {
+                    let mut reader = FASTQReader::with_source(args, parser, &chunk[..]);
+                    let res = (|| {
+                        loop {
+                            let read = match args.quality_profile {
+                                true => reader.next_read_with_phreds(&mut phreds)?,
+                                false => reader.next_read()?,
+                            };
+                            match read {
+                                Some(seq) => base_comp.extract_with_phreds(seq, &phreds)?,
+                                None => return Ok(()),
+                            }
+                        }
+                    })();
+                    reads_accepted += reader.reads_accepted();
+                    res.map_err(|e| (index, e))?;
+                }
+                Ok((base_comp, reads_accepted))
+            })
+        }).collect();
+
+        let mut res = Ok(());
+        for index in 0.. {
+            let parser = fastq_reader.curr.chunk_parser().unwrap();
+            let mut chunk = Vec::new();
+            match fastq_reader.curr.split_records(&mut fastq_reader.reader, &mut chunk, CHUNK_RECORDS) {
+                Ok(0) => break,
+                Ok(_) => {},
+                Err(e) => {
+                    res = Err(e.into());
+                    break;
+                },
+            }
+            // Sending only fails if all workers stopped, in which case they hold the error
+            if sender.send((index, parser, chunk)).is_err() {
+                break;
+            }
+        }
+        drop(sender);
+
+        // Chunks are taken in order, so the error of the earliest chunk is the first one in input
+        let mut base_comp = BaseComp::init(0);
+        let mut first_error: Option<(usize, Fastq2CompError)> = None;
+        for worker in workers {
+            match worker.join().unwrap() {
+                Ok((comp, reads_accepted)) => {
+                    base_comp.merge(&comp)?;
+                    fastq_reader.reads_accepted += reads_accepted;
+                },
+                Err((index, e)) => if first_error.as_ref().is_none_or(|(first, _)| index < *first) {
+                    first_error = Some((index, e));
+                },
+            }
+        }
+        if let Some((_, e)) = first_error {
+            return Err(e);
+        }
+        res.map(|_| base_comp)
+    })
+}
+
+/// Parses and checks records on the calling thread, passing reads in batches to `threads` workers which extract them.
+fn extract_batches_threaded<T: BufRead, S: RecordSource> (fastq_reader: &mut FASTQReader<T, S>, threads: usize) -> Result<BaseComp, Fastq2CompError> {
+    /// Reads per batch sent to a worker
+    const BATCH_SIZE: usize = 4096;
+
+    std::thread::scope(|scope| {
+        let (sender, receiver) = mpsc::sync_channel::<ReadBatch>(2 * threads);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let args = fastq_reader.sample_args;
+
+        let workers: Vec<_> = (0..threads).map(|_| {
+            let receiver = Arc::clone(&receiver);
+            scope.spawn(move || -> Result<BaseComp, Fastq2CompError> {
+                let mut base_comp = new_base_comp(&args);
+                // Lock is only held while waiting for the next batch
+                while let Ok(batch) = {let batch = receiver.lock().unwrap().recv(); batch} {
+                    for (seq, phreds) in batch.iter() {
+                        base_comp.extract_with_phreds(seq, phreds)?;
+                    }
+                }
+                Ok(base_comp)
+            })
+        }).collect();
+
+        let quality_profile = fastq_reader.sample_args.quality_profile;
+        let mut phreds = Vec::new();
+        let mut batch = ReadBatch::default();
+        let res = loop {
+            let read = match quality_profile {
+                true => fastq_reader.next_read_with_phreds(&mut phreds),
+                false => fastq_reader.next_read(),
+            };
+            match read {
+                Ok(Some(seq)) => batch.push(seq, &phreds),
+                Ok(None) => break Ok(()),
+                Err(e) => break Err(e),
+            }
+            // Sending only fails if all workers stopped, in which case they hold the error
+            if batch.len() == BATCH_SIZE && sender.send(std::mem::take(&mut batch)).is_err() {
+                break Ok(());
+            }
+        };
+        if !batch.is_empty() {
+            // Any error is returned by the workers below
+            let _ = sender.send(batch);
+        }
+        drop(sender);
+
+        let mut base_comp = BaseComp::init(0);
+        for worker in workers {
+            base_comp.merge(&worker.join().unwrap()?)?;
+        }
+        res.map(|_| base_comp)
+    })
+}
+
+/// Batch of reads and their Phred scores, stored contiguously to avoid an allocation per read
+#[derive(Default)]
+struct ReadBatch {
+    seqs: Vec<u8>,
+    /// End of each read in `seqs`
+    ends: Vec<usize>,
+    phreds: Vec<u8>,
+    /// End of the Phred scores of each read in `phreds`, which are empty for reads without them
+    phred_ends: Vec<usize>,
+}
+
+impl ReadBatch {
+    fn push (&mut self, seq: &[u8], phreds: &[u8]) {
+        self.seqs.extend_from_slice(seq);
+        self.ends.push(self.seqs.len());
+        self.phreds.extend_from_slice(phreds);
+        self.phred_ends.push(self.phreds.len());
+    }
+
+    fn len (&self) -> usize {
+        self.ends.len()
+    }
+
+    fn is_empty (&self) -> bool {
+        self.ends.is_empty()
+    }
+
+    /// Yields each read along with its Phred scores
+    fn iter (&self) -> impl Iterator<Item = (&[u8], &[u8])> {
+        ReadBatch::slices(&self.seqs, &self.ends).zip(ReadBatch::slices(&self.phreds, &self.phred_ends))
+    }
+
+    /// Splits `buf` at `ends`
+    fn slices<'a> (buf: &'a [u8], ends: &'a [usize]) -> impl Iterator<Item = &'a [u8]> {
+        let starts = std::iter::once(0).chain(ends.iter().copied());
+        starts.zip(ends.iter().copied()).map(move |(start, end)| &buf[start..end])
+    }
+}
 
-pub struct FASTQReader<T: BufRead> {
-    curr: FASTQRead,
+/// Reads records from input, yielding those passing the checks in `SampleArgs`.
+/// Reads FASTQ by default, other formats are read through their [`RecordSource`], e.g. FASTA with [`FASTQReader::fasta`].
+pub struct FASTQReader<T: BufRead, S: RecordSource = FASTQRead> {
+    curr: S,
     reader: T,
     sample_args: SampleArgs,
-    pub target_read_count: u64,
+    pub target_read_count: Option<u64>,
+    reads_accepted: u64,
+    /// Records read ahead while detecting the quality encoding, which are yet to be checked
+    lookahead: VecDeque<Lookahead>,
+    /// Record from `lookahead` last returned
+    replayed: Lookahead,
+    /// Set if the quality encoding was detected from the records read ahead, rather than given or mandated by the input format
+    encoding_detected: bool,
 }
 
 impl<T: BufRead> FASTQReader<T> {
     pub fn new (args: SampleArgs, reader: T) -> FASTQReader<T> {
-        let read = FASTQRead::new(args.trimmed_length);
-        let target_read_count = args.target_read_count;
+        let mut read = FASTQRead::new(args.trimmed_length);
+        read.mode = args.parse_mode;
+        read.colorspace = args.colorspace;
+
+        FASTQReader::with_source(args, read, reader)
+    }
+}
+
+impl<T: BufRead> FASTQReader<T, FASTARead> {
+    /// Reads (multi-line) FASTA input instead of FASTQ
+    pub fn fasta (args: SampleArgs, reader: T) -> FASTQReader<T, FASTARead> {
+        let mut read = FASTARead::new(args.trimmed_length);
+        read.mode = args.parse_mode;
+        read.colorspace = args.colorspace;
+
+        FASTQReader::with_source(args, read, reader)
+    }
+}
 
+impl<T: BufRead, S: RecordSource> FASTQReader<T, S> {
+    pub(crate) fn with_source (args: SampleArgs, source: S, reader: T) -> FASTQReader<T, S> {
         FASTQReader {
-            curr: read,
+            curr: source,
             reader,
             sample_args: args,
-            target_read_count,
+            target_read_count: args.target_read_count,
+            reads_accepted: 0,
+            lookahead: VecDeque::new(),
+            replayed: Lookahead::default(),
+            encoding_detected: false,
         }
     }
-    pub fn sample_random (self) -> Vec<String> {
-        let mut sampled_seqs = vec![String::new(); self.target_read_count as usize];
 
-        // Randomly sample FASTQ reads
-        sample(self, sampled_seqs.as_mut_slice());
-        sampled_seqs
+    /// Quality encoding of input, `None` if it is to be detected and no reads have been read yet.
+    pub fn quality_encoding (&self) -> Option<QualityEncoding> {
+        self.sample_args.quality_encoding
+    }
+
+    /// Reads ahead up to `QUALITY_DETECTION_RECORDS` records to detect the quality encoding of input,
+    /// unless it is set in `SampleArgs` or mandated by the input format.
+    fn detect_quality_encoding (&mut self) -> Result<(), Fastq2CompError> {
+        let encoding = match self.curr.quality_encoding() {
+            Some(encoding) => encoding,
+            None => {
+                let mut range = QualityRange::default();
+                while self.lookahead.len() < QUALITY_DETECTION_RECORDS && self.curr.read_record(&mut self.reader)?.is_some() {
+                    range.update(self.curr.quals());
+                    self.lookahead.push_back(Lookahead::new(&self.curr));
+                }
+                self.encoding_detected = true;
+                range.encoding()
+            },
+        };
+
+        self.sample_args.quality_encoding = Some(encoding);
+        Ok(())
+    }
+
+    /// Number of reads which have passed the checks in `SampleArgs` so far
+    pub fn reads_accepted (&self) -> u64 {
+        self.reads_accepted
+    }
+
+    /// Number of malformed records skipped so far (only in lenient parsing mode)
+    pub fn records_skipped (&self) -> u64 {
+        self.curr.records_skipped()
+    }
+
+    /// Reads until the next read passing the checks in `SampleArgs`, and returns it trimmed.
+    /// The returned slice borrows an internal buffer which is reused for every read, so no allocation takes place.
+    /// - Returns `Ok(None)` if EOF reached.
+    pub fn next_read (&mut self) -> Result<Option<&[u8]>, Fastq2CompError> {
+        self.next_record(None)
+    }
+
+    /// Like [`FASTQReader::next_read`], also decoding the Phred scores of the returned read into `phreds`.
+    /// `phreds` is left empty if the read has no qualities, e.g. in FASTA input.
+    pub fn next_read_with_phreds (&mut self, phreds: &mut Vec<u8>) -> Result<Option<&[u8]>, Fastq2CompError> {
+        self.next_record(Some(phreds))
+    }
+
+    fn next_record (&mut self, phreds: Option<&mut Vec<u8>>) -> Result<Option<&[u8]>, Fastq2CompError> {
+        if self.sample_args.quality_encoding.is_none() {
+            self.detect_quality_encoding()?;
+        }
+
+        let replayed = self.replay_lookahead()?;
+        if !replayed {
+            loop {
+                if self.curr.read_record(&mut self.reader)?.is_none() {
+                    return Ok(None);
+                }
+                let curr = &mut self.curr;
+                if check_detected(&mut self.sample_args, self.encoding_detected, |args| curr.check_read(args))? {break}
+            }
+        }
+        Ok(Some(self.accept(replayed, phreds)))
+    }
+
+    /// Like [`FASTQReader::next_record`], but only returns records read ahead while detecting the quality encoding,
+    /// so that the rest of input can be read from `reader` by other means.
+    fn next_lookahead_read (&mut self, phreds: Option<&mut Vec<u8>>) -> Result<Option<&[u8]>, Fastq2CompError> {
+        if self.sample_args.quality_encoding.is_none() {
+            self.detect_quality_encoding()?;
+        }
+
+        match self.replay_lookahead()? {
+            true => Ok(Some(self.accept(true, phreds))),
+            false => Ok(None),
+        }
+    }
+
+    /// Moves the next record read ahead which passes the checks to `replayed`, returning `false` if there is none.
+    fn replay_lookahead (&mut self) -> Result<bool, Fastq2CompError> {
+        while let Some(record) = self.lookahead.pop_front() {
+            if check_detected(&mut self.sample_args, self.encoding_detected, |args| record.check_read(args))? {
+                self.replayed = record;
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Counts the record which passed the checks, from `replayed` or `curr`, and returns its trimmed sequence.
+    fn accept (&mut self, replayed: bool, phreds: Option<&mut Vec<u8>>) -> &[u8] {
+        self.reads_accepted += 1;
+
+        let (seq, quals) = match replayed {
+            true => (&self.replayed.seq[..], self.replayed.quals.as_deref()),
+            false => (self.curr.seq(), self.curr.quals()),
+        };
+        if let Some(phreds) = phreds {
+            decode_phreds(quals, &self.sample_args, phreds);
+        }
+        FASTQRead::trim(seq, self.sample_args.trimmed_length).unwrap()
+    }
+
+    /// Randomly samples `target_read_count` reads, or returns all reads if it is `None`.
+    /// If fewer reads are available, all of them are returned.
+    /// Stops at, and returns, the first error encountered while reading.
+    pub fn sample_random (&mut self) -> Result<Vec<Vec<u8>>, Fastq2CompError> {
+        let target_read_count = match self.target_read_count {
+            Some(n) => n,
+            None => return self.collect(),
+        };
+
+        let mut reservoir = Reservoir::new(target_read_count, self.sample_args.seed);
+        while let Some(seq) = self.next_read()? {
+            if let Some(slot) = reservoir.offer() {
+                copy_into(slot, seq);
+            }
+        }
+        Ok(reservoir.into_sampled())
     }
 }
 
-impl<T: BufRead> Iterator for FASTQReader<T> {
-    type Item = String;
+/// Most slots allocated up front by a [`Reservoir`], so that a target far above the number of reads available
+/// doesn't allocate more than is sampled.
+const RESERVOIR_PREALLOCATED: usize = 1 << 16;
+
+/// Randomly samples `target` items of a stream with Algorithm L (Li, 1994), using a RNG seeded with `seed` if given.
+/// Items are offered one at a time, and the caller only copies an item into the reservoir if it is given a slot for it,
+/// reusing the buffers of the item it replaces, so items which aren't sampled cost nothing but a few random numbers.
+struct Reservoir<R> {
+    slots: Vec<R>,
+    target: usize,
+    rng: Pcg64,
+    /// Items offered so far
+    offered: u64,
+    /// 1-based index of the next item to be sampled once the reservoir is full
+    next: u64,
+    /// Largest of the random keys of the items sampled, as in Algorithm L
+    w: f64,
+}
 
-    fn next (&mut self) -> Option<String> {
-        loop {
-            self.curr.read_fastq(&mut self.reader)?;
-            if FASTQRead::check_read(&mut self.curr, &self.sample_args) {break}
+impl<R: Default> Reservoir<R> {
+    fn new (target: u64, seed: Option<u64>) -> Reservoir<R> {
+        let target = std::convert::TryFrom::try_from(target).unwrap_or(usize::MAX);
+        Reservoir {
+            slots: Vec::with_capacity(target.min(RESERVOIR_PREALLOCATED)),
+            target,
+            rng: match seed {
+                Some(seed) => Pcg64::seed_from_u64(seed),
+                None => Pcg64::from_entropy(),
+            },
+            offered: 0,
+            next: 0,
+            w: 1.0,
+        }
+    }
+
+    /// Offers the next item of the stream, returning the slot to store it in if it is sampled.
+    /// The slot holds the item it replaces, if any.
+    fn offer (&mut self) -> Option<&mut R> {
+        self.offered += 1;
+        if self.slots.len() < self.target {
+            self.slots.push(R::default());
+            if self.slots.len() == self.target {
+                self.skip();
+            }
+            return self.slots.last_mut();
+        }
+        if self.target == 0 || self.offered < self.next {
+            return None;
+        }
+
+        let slot = self.rng.gen_range(0..self.target);
+        self.skip();
+        Some(&mut self.slots[slot])
+    }
+
+    /// Draws how many items to skip before the next one to be sampled.
+    fn skip (&mut self) {
+        self.w *= (self.random().ln() / self.target as f64).exp();
+        let skipped = (self.random().ln() / (1.0 - self.w).ln()).floor();
+        self.next = self.offered.saturating_add(skipped as u64).saturating_add(1);
+    }
+
+    /// Uniformly random number in `(0, 1]`, so that its logarithm is finite
+    fn random (&mut self) -> f64 {
+        1.0 - self.rng.gen::<f64>()
+    }
+
+    /// Sampled items, all items offered if there were fewer than the target.
+    fn into_sampled (self) -> Vec<R> {
+        self.slots
+    }
+}
+
+/// Replaces the contents of `slot` with `data`, reusing its allocation.
+fn copy_into (slot: &mut Vec<u8>, data: &[u8]) {
+    slot.clear();
+    slot.extend_from_slice(data);
+}
+
+/// Yields owned copies of the reads returned by [`FASTQReader::next_read`].
+impl<T: BufRead, S: RecordSource> Iterator for FASTQReader<T, S> {
+    type Item = Result<Vec<u8>, Fastq2CompError>;
+
+    fn next (&mut self) -> Option<Self::Item> {
+        self.next_read().map(|read| read.map(<[u8]>::to_vec)).transpose()
+    }
+
+}
+
+/// Owned read 1 and read 2 of a pair
+pub type ReadPair = (Vec<u8>, Vec<u8>);
+
+/// Read 1 and read 2 of a pair, borrowed from a [`PairedFASTQReader`]
+pub type Mates<'a> = (&'a [u8], &'a [u8]);
+
+/// Reads pairs of FASTQ records, either from two inputs holding read 1 and read 2 in the same order,
+/// or from a single interleaved input in which every read 1 is directly followed by its mate.
+/// A pair is only accepted if both mates pass the checks in `SampleArgs`.
+///
+/// Malformed records are always an error, whatever the `parse_mode`, as skipping one would desynchronise the mates.
+pub struct PairedFASTQReader<T: BufRead> {
+    r1: FASTQRead,
+    r2: FASTQRead,
+    reader: T,
+    /// Input of read 2, `None` if `reader` is interleaved
+    mate_reader: Option<T>,
+    sample_args: SampleArgs,
+    pub target_read_count: Option<u64>,
+    reads_accepted: u64,
+    /// Pairs read ahead while detecting the quality encoding, which are yet to be checked
+    lookahead: VecDeque<(Lookahead, Lookahead)>,
+    /// Pair from `lookahead` last returned
+    replayed: (Lookahead, Lookahead),
+    /// Set if the quality encoding was detected from the pairs read ahead, rather than given
+    encoding_detected: bool,
+}
+
+impl<T: BufRead> PairedFASTQReader<T> {
+    /// Reads pairs from `r1_reader` and `r2_reader`, holding read 1 and read 2 respectively.
+    pub fn new (args: SampleArgs, r1_reader: T, r2_reader: T) -> PairedFASTQReader<T> {
+        PairedFASTQReader::with_readers(args, r1_reader, Some(r2_reader))
+    }
+
+    /// Reads pairs from a single interleaved input.
+    pub fn interleaved (args: SampleArgs, reader: T) -> PairedFASTQReader<T> {
+        PairedFASTQReader::with_readers(args, reader, None)
+    }
+
+    fn with_readers (args: SampleArgs, reader: T, mate_reader: Option<T>) -> PairedFASTQReader<T> {
+        let mate = || FASTQRead {colorspace: args.colorspace, ..FASTQRead::new(args.trimmed_length)};
+        PairedFASTQReader {
+            r1: mate(),
+            r2: mate(),
+            reader,
+            mate_reader,
+            sample_args: args,
+            target_read_count: args.target_read_count,
+            reads_accepted: 0,
+            lookahead: VecDeque::new(),
+            replayed: Default::default(),
+            encoding_detected: false,
         }
+    }
+
+    /// Quality encoding of input, `None` if it is to be detected and no pairs have been read yet.
+    pub fn quality_encoding (&self) -> Option<QualityEncoding> {
+        self.sample_args.quality_encoding
+    }
+
+    /// Number of pairs which have passed the checks in `SampleArgs` so far
+    pub fn reads_accepted (&self) -> u64 {
+        self.reads_accepted
+    }
+
+    /// Reads until the next pair of which both mates pass the checks in `SampleArgs`, and returns the mates trimmed.
+    /// As with [`FASTQReader::next_read`], the returned slices borrow internal buffers.
+    /// - Returns `Ok(None)` if EOF reached.
+    /// - Returns `Err(Fastq2CompError::UnpairedRecords)` if a mate is missing or the read names of the mates differ.
+    pub fn next_pair (&mut self) -> Result<Option<Mates<'_>>, Fastq2CompError> {
+        self.next_pair_record(None)
+    }
 
-        Some(FASTQRead::trim(&self.curr.seq, self.sample_args.trimmed_length).unwrap().to_string())
+    /// Like [`PairedFASTQReader::next_pair`], also decoding the Phred scores of read 1 and read 2 into `phreds`.
+    pub fn next_pair_with_phreds (&mut self, phreds: &mut (Vec<u8>, Vec<u8>)) -> Result<Option<Mates<'_>>, Fastq2CompError> {
+        self.next_pair_record(Some(phreds))
     }
 
+    fn next_pair_record (&mut self, phreds: Option<&mut (Vec<u8>, Vec<u8>)>) -> Result<Option<Mates<'_>>, Fastq2CompError> {
+        if self.sample_args.quality_encoding.is_none() {
+            // Detected from the qualities of both mates
+            let mut range = QualityRange::default();
+            while self.lookahead.len() < QUALITY_DETECTION_RECORDS && self.read_pair()? {
+                range.update(Some(&self.r1.quals));
+                range.update(Some(&self.r2.quals));
+                self.lookahead.push_back((Lookahead::new(&self.r1), Lookahead::new(&self.r2)));
+            }
+            self.sample_args.quality_encoding = Some(range.encoding());
+            self.encoding_detected = true;
+        }
+
+        let mut replayed = false;
+        while let Some((r1, r2)) = self.lookahead.pop_front() {
+            // Both mates are always checked, so colorspace data is found in either
+            let r1_passed = check_detected(&mut self.sample_args, self.encoding_detected, |args| r1.check_read(args))?;
+            let r2_passed = check_detected(&mut self.sample_args, self.encoding_detected, |args| r2.check_read(args))?;
+            if r1_passed && r2_passed {
+                self.replayed = (r1, r2);
+                replayed = true;
+                break;
+            }
+        }
+
+        if !replayed {
+            loop {
+                if !self.read_pair()? {
+                    return Ok(None);
+                }
+
+                let (mate1, mate2) = (&mut self.r1, &mut self.r2);
+                let r1_passed = check_detected(&mut self.sample_args, self.encoding_detected, |args| mate1.check_read(args))?;
+                let r2_passed = check_detected(&mut self.sample_args, self.encoding_detected, |args| mate2.check_read(args))?;
+                if r1_passed && r2_passed {break}
+            }
+        }
+        self.reads_accepted += 1;
+
+        let [(r1, r1_quals), (r2, r2_quals)] = match replayed {
+            true => [&self.replayed.0, &self.replayed.1].map(|mate| (&mate.seq[..], mate.quals.as_deref())),
+            false => [&self.r1, &self.r2].map(|mate| (&mate.seq[..], Some(&mate.quals[..]))),
+        };
+        if let Some((r1_phreds, r2_phreds)) = phreds {
+            decode_phreds(r1_quals, &self.sample_args, r1_phreds);
+            decode_phreds(r2_quals, &self.sample_args, r2_phreds);
+        }
+        let len = self.sample_args.trimmed_length;
+        Ok(Some((FASTQRead::trim(r1, len).unwrap(), FASTQRead::trim(r2, len).unwrap())))
+    }
+
+    /// Reads the next pair into `r1` and `r2`, checking that the mates match up.
+    /// Returns `false` if EOF reached.
+    fn read_pair (&mut self) -> Result<bool, Fastq2CompError> {
+        let r1 = self.r1.read_fastq(&mut self.reader)?;
+        let r2 = match &mut self.mate_reader {
+            Some(mate_reader) => self.r2.read_fastq(mate_reader)?,
+            None => {
+                // Both mates come from the same input, so keep record indices and offsets in step
+                self.r2.record = self.r1.record;
+                self.r2.offset = self.r1.offset;
+                let r2 = self.r2.read_fastq(&mut self.reader)?;
+                self.r1.record = self.r2.record;
+                self.r1.offset = self.r2.offset;
+                r2
+            },
+        };
+
+        match (r1, r2) {
+            (None, None) => return Ok(false),
+            (Some(_), None) => return Err(Fastq2CompError::UnpairedRecords {
+                record: self.r1.record, reason: "read 2 is missing".to_string(),
+            }),
+            (None, Some(_)) => return Err(Fastq2CompError::UnpairedRecords {
+                record: self.r2.record, reason: "read 1 is missing".to_string(),
+            }),
+            (Some(_), Some(_)) => {},
+        }
+        if self.r1.name() != self.r2.name() {
+            return Err(Fastq2CompError::UnpairedRecords {
+                record: self.r2.record,
+                reason: format!(
+                    "read names {:?} and {:?} differ",
+                    String::from_utf8_lossy(self.r1.name()), String::from_utf8_lossy(self.r2.name())
+                ),
+            });
+        }
+
+        Ok(true)
+    }
+
+    /// Randomly samples `target_read_count` pairs, or returns all pairs if it is `None`.
+    /// Pairs are sampled jointly, so both mates of a pair are either sampled or not.
+    /// If fewer pairs are available, all of them are returned.
+    /// Stops at, and returns, the first error encountered while reading.
+    pub fn sample_random (&mut self) -> Result<Vec<ReadPair>, Fastq2CompError> {
+        let target_read_count = match self.target_read_count {
+            Some(n) => n,
+            None => return self.collect(),
+        };
+
+        let mut reservoir = Reservoir::<ReadPair>::new(target_read_count, self.sample_args.seed);
+        while let Some((r1, r2)) = self.next_pair()? {
+            if let Some((r1_slot, r2_slot)) = reservoir.offer() {
+                copy_into(r1_slot, r1);
+                copy_into(r2_slot, r2);
+            }
+        }
+        Ok(reservoir.into_sampled())
+    }
+}
+
+/// Yields owned copies of the pairs returned by [`PairedFASTQReader::next_pair`].
+impl<T: BufRead> Iterator for PairedFASTQReader<T> {
+    type Item = Result<ReadPair, Fastq2CompError>;
+
+    fn next (&mut self) -> Option<Self::Item> {
+        self.next_pair().map(|pair| pair.map(|(r1, r2)| (r1.to_vec(), r2.to_vec()))).transpose()
+    }
 }
@@ -0,0 +1,442 @@
+use crate::{percentages, Alphabet, Counted, Fastq2CompError, Rounding};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+
+#[cfg(test)]
+mod test_kmer_comp {
+    use super::*;
+
+    #[test]
+    fn test_kmer_index() {
+        assert_eq!(kmer_index(b"A"), Some(0));
+        assert_eq!(kmer_index(b"CG"), Some(6));
+        assert_eq!(kmer_index(b"TTT"), Some(63));
+        assert_eq!(kmer_index(b"ANA"), None);
+        for kmer in [&b"ACGTAC"[..], b"GATTACA", b"T"].iter() {
+            assert_eq!(kmer_string(kmer_index(kmer).unwrap(), kmer.len()).as_bytes(), *kmer);
+        }
+    }
+
+    #[test]
+    fn test_extract() {
+        let mut comp = KmerComp::new(2, Alphabet::default());
+        comp.extract(b"ACGT");
+        comp.extract(b"ACNTT");
+        comp.extract(b"A");
+
+        // a column for each base, the last k - 1 of which have no k-mers starting there
+        assert_eq!(comp.len(), 5);
+        assert_eq!(comp.reads_read(), 3);
+        assert_eq!(comp.lib.iter().map(|col| col.coverage).collect::<Vec<_>>(), vec![2, 1, 1, 1, 0]);
+        assert_eq!(comp.lib[0].count(b"AC"), 2);
+        assert_eq!(comp.lib[1].count(b"CG"), 1);
+        assert_eq!(comp.lib[3].count(b"TT"), 1);
+
+        // characters are read as the alphabet says
+        let mut comp = KmerComp::new(3, "lowercase,u".parse().unwrap());
+        comp.extract(b"cgU");
+        assert_eq!(comp.lib[0].count(b"CGT"), 1);
+    }
+
+    #[test]
+    fn test_percentages() {
+        let mut comp = KmerComp::new(1, Alphabet::default());
+        for read in [&b"A"[..], b"A", b"C"].iter() {
+            comp.extract(read);
+        }
+
+        comp.compute_percentages(Rounding::Truncate);
+        assert_eq!(comp.lib[0].percentages, Some(vec![66, 33, 0, 0]));
+        comp.compute_percentages(Rounding::LargestRemainder);
+        assert_eq!(comp.lib[0].percentages, Some(vec![67, 33, 0, 0]));
+        assert_eq!(comp.lib[0].fractions.as_ref().unwrap()[0], 2.0 / 3.0);
+
+        // columns without k-mers, e.g. the last k - 1 of a read, have neither percentages nor fractions
+        let mut comp = KmerComp::new(2, Alphabet::default());
+        comp.extract(b"ACG");
+        comp.compute_percentages(Rounding::Truncate);
+        assert!(comp.lib[1].percentages.is_some());
+        assert_eq!((&comp.lib[2].percentages, &comp.lib[2].fractions), (&None, &None));
+
+        let json = serde_json::to_string(&comp).unwrap();
+        assert!(json.contains("{\"pos\":3,\"coverage\":0,\"counts\":{},\"percentages\":null,\"fractions\":null}"), "{}", json);
+        assert_eq!(serde_json::from_str::<KmerComp>(&json).unwrap(), comp);
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut a = KmerComp::new(2, Alphabet::default());
+        a.extract(b"ACG");
+        let mut b = KmerComp::new(2, Alphabet::default());
+        b.extract(b"ACGTT");
+        b.extract(b"GG");
+
+        a.merge(&b).unwrap();
+        assert_eq!((a.len(), a.reads_read()), (5, 3));
+        assert_eq!(a.lib[0].count(b"AC"), 2);
+        assert_eq!(a.lib[0].coverage, 3);
+
+        let c = KmerComp::new(3, Alphabet::default());
+        assert!(matches!(a.merge(&c), Err(Fastq2CompError::MismatchedKmerLength {k: Some(2), other: Some(3)})));
+
+        // the alphabet is kept through JSON, and must match
+        let mut lowercase = KmerComp::new(2, "lowercase".parse().unwrap());
+        lowercase.extract(b"acg");
+        let parsed: KmerComp = serde_json::from_str(&serde_json::to_string(&lowercase).unwrap()).unwrap();
+        assert_eq!(parsed.alphabet, lowercase.alphabet);
+        assert!(matches!(a.merge(&parsed), Err(Fastq2CompError::MismatchedSampleArgs {field: "alphabet"})));
+        assert_eq!(a.reads_read(), 3);
+        lowercase.merge(&parsed).unwrap();
+        assert_eq!(lowercase.lib[0].count(b"AC"), 2);
+    }
+
+    #[test]
+    fn test_json() {
+        let mut comp = KmerComp::new(2, Alphabet::default());
+        comp.extract(b"ACGT");
+        comp.extract(b"ACTT");
+        comp.compute_percentages(Rounding::Truncate);
+
+        let json = serde_json::to_string(&comp).unwrap();
+        assert!(json.starts_with("{\"k\":2,\"lib\":[{\"pos\":1,\"coverage\":2,\"counts\":{\"AC\":2},\"percentages\":{\"AC\":100},\"fractions\":{\"AC\":1.0}}"), "{}", json);
+
+        // k-mers with 0 counts aren't output, but are read back
+        let parsed: KmerComp = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, comp);
+
+        let col = |counts: &str| format!("{{\"k\":1,\"lib\":[{{\"pos\":1,\"coverage\":1,\"counts\":{}}}],\"reads_read\":1,\"alphabet\":{{}}}}", counts);
+        assert!(serde_json::from_str::<KmerComp>(&col("{\"A\":1}")).is_ok());
+        assert!(serde_json::from_str::<KmerComp>(&col("{\"N\":1}")).is_err());
+        assert!(serde_json::from_str::<KmerComp>(&col("{\"AC\":1}")).is_err());
+        assert!(serde_json::from_str::<KmerComp>(&col("{\"A\":1,\"AC\":1}")).is_err());
+        assert!(serde_json::from_str::<KmerComp>(&col("{\"\":1}")).is_err());
+
+        // k-mers shorter than k aren't read as other k-mers
+        let col = |values: &str| format!("{{\"k\":2,\"lib\":[{{\"pos\":1,\"coverage\":5,{}}}],\"reads_read\":5,\"alphabet\":{{}}}}", values);
+        assert!(serde_json::from_str::<KmerComp>(&col("\"counts\":{\"C\":5}")).is_err());
+        assert!(serde_json::from_str::<KmerComp>(&col("\"counts\":{\"AC\":5},\"percentages\":{\"C\":100}")).is_err());
+        assert!(serde_json::from_str::<KmerComp>(&col("\"counts\":{\"AC\":5},\"fractions\":{\"ACG\":1.0}")).is_err());
+        assert_eq!(serde_json::from_str::<KmerComp>(&col("\"counts\":{\"AC\":5}")).unwrap().lib[0].count(b"AC"), 5);
+    }
+
+    #[test]
+    fn test_oversized_kmer() {
+        // k-mers too long to count are rejected before allocating 4^k values for them
+        let kmer = "A".repeat(40);
+        let comp = format!("{{\"k\":2,\"lib\":[{{\"pos\":1,\"coverage\":1,\"counts\":{{\"{}\":1}}}}],\"reads_read\":1,\"alphabet\":{{}}}}", kmer);
+        assert!(serde_json::from_str::<KmerComp>(&comp).is_err());
+
+        let mut output = crate::extract_comp::Output::new(crate::BaseComp::init(1), Default::default()).to_json().unwrap();
+        output.insert_str(output.rfind("\"reads_read\"").unwrap(), &format!("\"kmers\":{},", comp));
+        assert!(crate::BaseComp::from_json(&output).is_err());
+        assert!(crate::BaseComp::from_json(&output.replace(&kmer, "AC")).is_ok());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_too_long() {
+        KmerComp::new(MAX_K + 1, Alphabet::default());
+    }
+}
+
+/// Longest k-mers which can be counted, as there are `4^k` of them at each position.
+pub const MAX_K: usize = 6;
+
+/// Represents the k-mer composition of reads, for example dinucleotides (`k = 2`) to find CpG context in bisulfite libraries.
+/// As a Vec of `KmerCompCol`(umns), each of which counts the k-mers starting at its position.
+/// Like [`crate::BaseComp`], columns are added as longer reads are extracted, and percentages are derived from the counts
+/// by [`KmerComp::compute_percentages`].
+///
+/// Only k-mers of `ACGT` are counted, so k-mers including `N` or other characters are skipped.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(try_from = "KmerCompData")]
+pub struct KmerComp {
+    k: usize,
+    pub lib: Vec<KmerCompCol>,
+    /// Reads the k-mer compositions were extracted from.
+    reads_read: u64,
+
+    /// How characters of reads are read, see [`crate::BaseComp::with_alphabet`].
+    /// Output, so that compositions read back from JSON are only merged with ones extracted with the same alphabet.
+    alphabet: Alphabet,
+}
+
+/// Represents a column of k-mer composition data: the k-mers starting at a position.
+/// Counts, percentages and fractions are indexed by k-mer, with each base taking 2 bits (`A` 0, `C` 1, `G` 2, `T` 3)
+/// and the first base the most significant ones. In JSON, they are maps from k-mer to value, leaving out k-mers of value 0.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct KmerCompCol {
+    pub pos: usize,
+    /// Number of k-mers counted starting at this position
+    pub coverage: u64,
+    /// Raw count of each k-mer
+    #[serde(with = "kmer_map")]
+    pub counts: Vec<usize>,
+    /// Percentage of each k-mer, `None` if the column has no k-mers.
+    #[serde(default, with = "kmer_map::option")]
+    pub percentages: Option<Vec<usize>>,
+    /// Fraction of each k-mer, `None` if the column has no k-mers.
+    #[serde(default, with = "kmer_map::option")]
+    pub fractions: Option<Vec<f64>>,
+}
+
+impl KmerCompCol {
+    pub fn new (pos: usize, k: usize) -> KmerCompCol {
+        KmerCompCol {pos, coverage: 0, counts: vec![0; 1 << (2 * k)], percentages: None, fractions: None}
+    }
+
+    /// Returns the count of `kmer`, 0 if it isn't a k-mer of `ACGT` of the length counted.
+    pub fn count (&self, kmer: &[u8]) -> usize {
+        match kmer_index(kmer) {
+            Some(index) if 1 << (2 * kmer.len()) == self.counts.len() => self.counts[index],
+            _ => 0,
+        }
+    }
+
+    /// Sets `percentages` and `fractions` from the current k-mer counts.
+    pub fn compute_percentages (&mut self, rounding: Rounding) {
+        let sum = self.counts.iter().sum::<usize>();
+        if sum == 0 {
+            self.percentages = None;
+            self.fractions = None;
+            return;
+        }
+
+        self.percentages = Some(percentages(&self.counts, rounding));
+        self.fractions = Some(self.counts.iter().map(|&count| count as f64 / sum as f64).collect());
+    }
+}
+
+impl KmerComp {
+    /// Returns an empty k-mer composition of k-mers of length `k`, reading characters of reads as `alphabet` says.
+    ///
+    /// Panics if `k` is 0 or greater than [`MAX_K`], see [`KmerComp::check_k`].
+    pub fn new (k: usize, alphabet: Alphabet) -> KmerComp {
+        KmerComp::check_k(k).unwrap();
+        KmerComp {k, lib: Vec::new(), reads_read: 0, alphabet}
+    }
+
+    /// Returns `Err(Fastq2CompError::InvalidKmerLength)` if k-mers of length `k` can't be counted.
+    pub fn check_k (k: usize) -> Result<(), Fastq2CompError> {
+        match k {
+            1..=MAX_K => Ok(()),
+            _ => Err(Fastq2CompError::InvalidKmerLength {k, max: MAX_K}),
+        }
+    }
+
+    pub fn k (&self) -> usize {
+        self.k
+    }
+
+    pub fn reads_read (&self) -> u64 {
+        self.reads_read
+    }
+
+    pub fn len (&self) -> usize {
+        self.lib.len()
+    }
+
+    pub fn is_empty (&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Adds the k-mers of a read to the composition.
+    /// A read of length `n` has `n - k + 1` k-mers, but a column is added for each of its bases,
+    /// so that columns line up with those of [`crate::BaseComp`].
+    pub fn extract (&mut self, s: &[u8]) {
+        for pos in self.lib.len() + 1..=s.len() {
+            self.lib.push(KmerCompCol::new(pos, self.k));
+        }
+
+        let mask = (1 << (2 * self.k)) - 1;
+        // Index of the k-mer ending at the current base, and the number of bases of it which are ACGT
+        let (mut index, mut valid) = (0, 0);
+        for (i, &c) in s.iter().enumerate() {
+            match self.alphabet.count_as(c).and_then(|counted| match counted {
+                Counted::Base(base) => base_code(base),
+                Counted::Other => None,
+            }) {
+                Some(code) => {
+                    index = ((index << 2) | code) & mask;
+                    valid += 1;
+                },
+                None => valid = 0,
+            }
+
+            if valid >= self.k {
+                let col = &mut self.lib[i + 1 - self.k];
+                col.counts[index] += 1;
+                col.coverage += 1;
+            }
+        }
+        self.reads_read += 1;
+    }
+
+    /// Adds the k-mer counts of `other` to this composition, matching up columns by position as [`crate::BaseComp::merge`] does.
+    /// Percentages need to be recomputed afterwards using [`KmerComp::compute_percentages`].
+    /// Returns `Err` if `other` can't be merged, see [`KmerComp::check_merge`].
+    pub fn merge (&mut self, other: &KmerComp) -> Result<(), Fastq2CompError> {
+        self.check_merge(other)?;
+
+        for pos in self.lib.len() + 1..=other.lib.len() {
+            self.lib.push(KmerCompCol::new(pos, self.k));
+        }
+        for (col, other) in self.lib.iter_mut().zip(other.lib.iter()) {
+            col.coverage += other.coverage;
+            for (count, other) in col.counts.iter_mut().zip(other.counts.iter()) {
+                *count += other;
+            }
+        }
+
+        self.reads_read += other.reads_read;
+        Ok(())
+    }
+
+    /// Returns `Err(Fastq2CompError::MismatchedKmerLength)` if `other` counts k-mers of another length,
+    /// and `Err(Fastq2CompError::MismatchedSampleArgs)` if it was extracted reading characters with another alphabet.
+    pub fn check_merge (&self, other: &KmerComp) -> Result<(), Fastq2CompError> {
+        if self.k != other.k {
+            return Err(Fastq2CompError::MismatchedKmerLength {k: Some(self.k), other: Some(other.k)});
+        }
+        if self.alphabet != other.alphabet {
+            return Err(Fastq2CompError::MismatchedSampleArgs {field: "alphabet"});
+        }
+        Ok(())
+    }
+
+    /// Sets the percentages and fractions of every column from their k-mer counts.
+    pub fn compute_percentages (&mut self, rounding: Rounding) {
+        for col in self.lib.iter_mut() {
+            col.compute_percentages(rounding);
+        }
+    }
+}
+
+/// [`KmerComp`] as read from JSON, before checking that its k-mers are of length `k`
+#[derive(Deserialize)]
+struct KmerCompData {
+    k: usize,
+    lib: Vec<KmerCompCol>,
+    reads_read: u64,
+    alphabet: Alphabet,
+}
+
+impl TryFrom<KmerCompData> for KmerComp {
+    type Error = String;
+
+    fn try_from (data: KmerCompData) -> Result<Self, Self::Error> {
+        KmerComp::check_k(data.k).map_err(|e| e.to_string())?;
+        let len = 1 << (2 * data.k);
+
+        let mut lib = data.lib;
+        for col in lib.iter_mut() {
+            // Maps of k-mers of length other than k would be read as 4^length values, which resizing would re-index
+            let lens = [Some(col.counts.len()), col.percentages.as_ref().map(Vec::len), col.fractions.as_ref().map(Vec::len)];
+            if lens.iter().flatten().any(|&n| n != 0 && n != len) {
+                return Err(format!("k-mers at position {} aren't of length k = {}", col.pos, data.k));
+            }
+            // JSON leaves out k-mers of value 0, including any at the end
+            col.counts.resize(len, 0);
+            if let Some(percentages) = &mut col.percentages {
+                percentages.resize(len, 0);
+            }
+            if let Some(fractions) = &mut col.fractions {
+                fractions.resize(len, 0.0);
+            }
+        }
+
+        Ok(KmerComp {k: data.k, lib, reads_read: data.reads_read, alphabet: data.alphabet})
+    }
+}
+
+/// 2-bit code of a base, `None` if it isn't one of `ACGT`
+fn base_code (base: u8) -> Option<usize> {
+    match base {
+        b'A' => Some(0),
+        b'C' => Some(1),
+        b'G' => Some(2),
+        b'T' => Some(3),
+        _ => None,
+    }
+}
+
+/// Index of a k-mer of `ACGT` into the counts of a column, `None` if it has other characters.
+fn kmer_index (kmer: &[u8]) -> Option<usize> {
+    kmer.iter().try_fold(0, |index, &base| Some((index << 2) | base_code(base)?))
+}
+
+/// k-mer of length `k` at `index` into the counts of a column
+fn kmer_string (index: usize, k: usize) -> String {
+    (0..k).rev().map(|i| ['A', 'C', 'G', 'T'][(index >> (2 * i)) & 3]).collect()
+}
+
+/// (De)serialises values indexed by k-mer as a map from k-mer to value, leaving out values of 0.
+mod kmer_map {
+    use super::{kmer_index, kmer_string, MAX_K};
+    use serde::de::{Deserialize, Deserializer, Error};
+    use serde::ser::{Serialize, Serializer};
+    use std::collections::BTreeMap;
+
+    pub fn serialize<S, T> (values: &[T], serializer: S) -> Result<S::Ok, S::Error>
+    where S: Serializer, T: Serialize + Default + PartialEq
+    {
+        // values.len() is 4^k
+        let k = values.len().trailing_zeros() as usize / 2;
+        serializer.collect_map(values.iter().enumerate()
+            .filter(|(_, value)| **value != T::default())
+            .map(|(index, value)| (kmer_string(index, k), value)))
+    }
+
+    /// Returns `4^k` values, or none if the map is empty, with `k` the length of the k-mers in the map, which must all be the same.
+    /// k-mers are checked before allocating, so that untrusted JSON can't ask for `4^k` values of a huge `k`.
+    pub fn deserialize<'de, D, T> (deserializer: D) -> Result<Vec<T>, D::Error>
+    where D: Deserializer<'de>, T: Deserialize<'de> + Default + Clone
+    {
+        let mut values = Vec::new();
+        let mut k = None;
+        for (kmer, value) in BTreeMap::<String, T>::deserialize(deserializer)? {
+            if kmer.is_empty() || kmer.len() > MAX_K {
+                return Err(D::Error::custom(format!("k-mer {:?} isn't of length 1 to {}", kmer, MAX_K)));
+            }
+            if *k.get_or_insert(kmer.len()) != kmer.len() {
+                return Err(D::Error::custom(format!("k-mer {:?} differs in length from the others", kmer)));
+            }
+            let index = kmer_index(kmer.as_bytes()).ok_or_else(|| D::Error::custom(format!("invalid k-mer {:?}", kmer)))?;
+
+            if values.is_empty() {
+                values.resize(1 << (2 * kmer.len()), T::default());
+            }
+            values[index] = value;
+        }
+        Ok(values)
+    }
+
+    /// (De)serialises values which may be missing as a map like [`kmer_map`](super::kmer_map), or `null`.
+    pub mod option {
+        use serde::de::{Deserialize, Deserializer};
+        use serde::ser::{Serialize, Serializer};
+
+        pub fn serialize<S, T> (values: &Option<Vec<T>>, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer, T: Serialize + Default + PartialEq
+        {
+            match values {
+                Some(values) => super::serialize(values, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D, T> (deserializer: D) -> Result<Option<Vec<T>>, D::Error>
+        where D: Deserializer<'de>, T: Deserialize<'de> + Default + Clone
+        {
+            struct Values<T>(Vec<T>);
+
+            impl<'de, T: Deserialize<'de> + Default + Clone> Deserialize<'de> for Values<T> {
+                fn deserialize<D: Deserializer<'de>> (deserializer: D) -> Result<Self, D::Error> {
+                    super::deserialize(deserializer).map(Values)
+                }
+            }
+
+            Ok(Option::<Values<T>>::deserialize(deserializer)?.map(|values| values.0))
+        }
+    }
+}
@@ -1,11 +1,9 @@
 pub mod extract_comp;
+pub mod kmer_comp;
+pub mod sam;
+mod error;
 
-
-/// Extracted as function as it will immediately terminate, allowing no destructors to run.
-/// Therefore, make sure destructors are run before terminating.
-pub fn exit () {
-    std::process::exit(1);
-}
+pub use error::Fastq2CompError;
 
 pub mod test_utils {
     use std::io::Cursor;
@@ -31,45 +29,434 @@ pub mod io_utils {
     use std::path::PathBuf;
     use std::fs::OpenOptions;
     use std::io::{self, BufReader, BufRead, Write, Read};
-    use flate2::read::GzDecoder;
+    use flate2::bufread::MultiGzDecoder;
+
+    #[cfg(test)]
+    mod test_compression {
+        use super::*;
+        use std::io::Cursor;
+
+        #[test]
+        fn test_detect() {
+            assert_eq!(Compression::detect(b"@r1\nACGT"), Compression::None);
+            assert_eq!(Compression::detect(b"\x1f\x8b\x08\x00"), Compression::Gzip);
+            assert_eq!(Compression::detect(b"BZh91AY"), Compression::Bzip2);
+            assert_eq!(Compression::detect(b"\xfd7zXZ\x00\x00"), Compression::Xz);
+            assert_eq!(Compression::detect(b"\x28\xb5\x2f\xfd"), Compression::Zstd);
+            assert_eq!(Compression::detect(b""), Compression::None);
+        }
+
+        #[test]
+        fn test_multi_member_gzip() {
+            // Two gzip members, as in BGZF or concatenated .gz files
+            let mut data = Vec::new();
+            for part in [&b"@r1\nAC"[..], &b"GT\n"[..]].iter() {
+                let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(part).unwrap();
+                data.extend(encoder.finish().unwrap());
+            }
+
+            let mut out = String::new();
+            decompressed_reader(Cursor::new(data)).unwrap().read_to_string(&mut out).unwrap();
+            assert_eq!(out, "@r1\nACGT\n");
+
+            let mut out = String::new();
+            decompressed_reader(Cursor::new(b"@r1\n")).unwrap().read_to_string(&mut out).unwrap();
+            assert_eq!(out, "@r1\n");
+        }
+
+        #[cfg(all(feature = "bzip2", feature = "xz", feature = "zstd"))]
+        #[test]
+        fn test_other_codecs() {
+            let data = b"@r1\nACGT\n+\nIIII\n";
+            let mut bz = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+            bz.write_all(data).unwrap();
+            let mut xz = xz2::write::XzEncoder::new(Vec::new(), 6);
+            xz.write_all(data).unwrap();
+
+            for compressed in [bz.finish().unwrap(), xz.finish().unwrap(), zstd::encode_all(&data[..], 0).unwrap()].iter() {
+                let mut out = Vec::new();
+                decompressed_reader(Cursor::new(compressed.clone())).unwrap().read_to_end(&mut out).unwrap();
+                assert_eq!(out, data);
+            }
+        }
+
+        /// Compresses `data` as BGZF, in blocks of `block_size` uncompressed bytes, followed by the EOF block
+        fn bgzf(data: &[u8], block_size: usize) -> Vec<u8> {
+            let mut out = Vec::new();
+            for block in data.chunks(block_size).chain(std::iter::once(&[][..])) {
+                let mut encoder = flate2::GzBuilder::new()
+                    .extra(vec![b'B', b'C', 2, 0, 0, 0])
+                    .write(Vec::new(), flate2::Compression::default());
+                encoder.write_all(block).unwrap();
+                let mut member = encoder.finish().unwrap();
+                let bsize = (member.len() - 1) as u16;
+                member[16..18].copy_from_slice(&bsize.to_le_bytes());
+                out.extend(member);
+            }
+            out
+        }
+
+        #[test]
+        fn test_bgzf() {
+            let data: Vec<u8> = (0..100_000u32).flat_map(|i| i.to_string().into_bytes()).collect();
+            let compressed = bgzf(&data, 1000);
+            assert!(is_bgzf(&compressed));
+            assert!(!is_bgzf(&compressed[..10]));
+
+            for threads in [1, 2, 3, 8].iter() {
+                let mut out = Vec::new();
+                parallel_decompressed_reader(Cursor::new(compressed.clone()), *threads).unwrap().read_to_end(&mut out).unwrap();
+                assert_eq!(out, data);
+            }
+
+            // truncated input
+            let mut out = Vec::new();
+            let mut reader = BgzfReader::new(Cursor::new(&compressed[..compressed.len() - 40]), 2);
+            assert_eq!(reader.read_to_end(&mut out).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        }
+
+        #[cfg(not(feature = "zstd"))]
+        #[test]
+        fn test_unsupported() {
+            let err = decompressed_reader(Cursor::new(b"\x28\xb5\x2f\xfd")).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        }
+    }
+
+    /// Compression formats of input, recognised by their magic bytes.
+    /// Only gzip is always supported, other formats need the cargo feature of the same name.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Compression {
+        None,
+        /// Gzip, including streams of multiple members such as BGZF
+        Gzip,
+        Bzip2,
+        Xz,
+        Zstd,
+    }
+
+    impl Compression {
+        /// Detects compression format from the first bytes of a stream
+        pub fn detect(start: &[u8]) -> Compression {
+            if start.starts_with(&[0x1f, 0x8b]) {
+                Compression::Gzip
+            } else if start.starts_with(b"BZh") {
+                Compression::Bzip2
+            } else if start.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+                Compression::Xz
+            } else if start.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+                Compression::Zstd
+            } else {
+                Compression::None
+            }
+        }
+    }
+
+    /// Returns compression format of the stream, detected from its magic bytes.
+    /// Only peeks into the buffer, so no data is consumed from `reader`.
+    pub fn detect_compression<T: BufRead>(reader: &mut T) -> io::Result<Compression> {
+        Ok(Compression::detect(reader.fill_buf()?))
+    }
 
     // Reader is a wrapper over BufRead
     // Takes in a PathBuf and open it or if no PathBuf is provided, opens up stdin
     // And provides an interface over the actual reading.
-    pub fn compressed_reader<T: Read + 'static>(reader: T, compressed: bool) -> Box<dyn BufRead> {
-        Box::new(BufReader::new(
-            if compressed {Box::new(GzDecoder::new(reader))} else {Box::new(reader) as Box<dyn Read>}
-        ))
+    // Returns an error of kind `Unsupported` if support for `compression` was not compiled in.
+    pub fn compressed_reader<T: BufRead + 'static>(reader: T, compression: Compression) -> io::Result<Box<dyn BufRead>> {
+        let unsupported = |feature: &str| Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{} compressed input is not supported, build with the `{}` feature", feature, feature),
+        ));
+
+        let reader: Box<dyn Read> = match compression {
+            Compression::None => return Ok(Box::new(reader)),
+            Compression::Gzip => Box::new(MultiGzDecoder::new(reader)),
+            #[cfg(feature = "bzip2")]
+            Compression::Bzip2 => Box::new(bzip2::bufread::MultiBzDecoder::new(reader)),
+            #[cfg(feature = "xz")]
+            Compression::Xz => Box::new(xz2::bufread::XzDecoder::new_multi_decoder(reader)),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Box::new(zstd::stream::read::Decoder::with_buffer(reader)?),
+            #[allow(unreachable_patterns)]
+            Compression::Bzip2 => return unsupported("bzip2"),
+            #[allow(unreachable_patterns)]
+            Compression::Xz => return unsupported("xz"),
+            #[allow(unreachable_patterns)]
+            Compression::Zstd => return unsupported("zstd"),
+        };
+        Ok(Box::new(BufReader::new(reader)))
     }
 
-    use std::io::ErrorKind;
-    /// Will return writer to File if PathBuf can be opened, will panic if File unavailable
-    /// And return writer to stdout if PathBuf not given
-    pub fn get_writer(output: &Option<PathBuf>) -> Box<dyn Write> {
-        match output {
-            Some(file) => Box::new(OpenOptions::new().append(true).open(&file).unwrap_or_else(|error| {
-                if error.kind() == ErrorKind::NotFound {
-                    OpenOptions::new().create(true).write(true).open(&file).expect("Problem creating the file!")
-                } else {
-                    panic!("Problem opening the file: {:?}", error);
+    /// Returns `reader` decompressed according to the compression format detected from its magic bytes.
+    pub fn decompressed_reader<T: BufRead + 'static>(mut reader: T) -> io::Result<Box<dyn BufRead>> {
+        let compression = detect_compression(&mut reader)?;
+        compressed_reader(reader, compression)
+    }
+
+    /// Returns true if the stream starts with a BGZF block: a gzip member with the `BC` extra subfield.
+    pub fn is_bgzf(start: &[u8]) -> bool {
+        start.len() >= 18 && start.starts_with(&[0x1f, 0x8b, 0x08]) && start[3] & 0x04 != 0 && &start[12..14] == b"BC"
+    }
+
+    /// Like [`decompressed_reader`], but BGZF input is decompressed using `threads` threads.
+    pub fn parallel_decompressed_reader<T: BufRead + 'static>(mut reader: T, threads: usize) -> io::Result<Box<dyn BufRead>> {
+        if threads > 1 && is_bgzf(reader.fill_buf()?) {
+            return Ok(Box::new(BgzfReader::new(reader, threads)));
+        }
+        decompressed_reader(reader)
+    }
+
+    /// Decompresses BGZF input, the blocked gzip format used by BAM and `bgzip`.
+    /// As blocks are independent gzip members, batches of them are decompressed in parallel.
+    pub struct BgzfReader<R: BufRead> {
+        inner: R,
+        threads: usize,
+        /// Decompressed data of the last batch of blocks
+        buf: Vec<u8>,
+        pos: usize,
+    }
+
+    impl<R: BufRead> BgzfReader<R> {
+        /// Blocks decompressed by each thread per batch
+        const BLOCKS_PER_THREAD: usize = 16;
+
+        pub fn new(inner: R, threads: usize) -> BgzfReader<R> {
+            BgzfReader { inner, threads: threads.max(1), buf: Vec::new(), pos: 0 }
+        }
+
+        /// Reads the next block, returning `None` at EOF.
+        fn read_block(&mut self) -> io::Result<Option<Vec<u8>>> {
+            if self.inner.fill_buf()?.is_empty() {
+                return Ok(None);
+            }
+
+            let mut block = vec![0; 18];
+            self.inner.read_exact(&mut block)?;
+            if !is_bgzf(&block) {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "gzip member is not a BGZF block"));
+            }
+            let bsize = u16::from_le_bytes([block[16], block[17]]) as usize + 1;
+            if bsize < 18 {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "BGZF block size is too small"));
+            }
+            block.resize(bsize, 0);
+            self.inner.read_exact(&mut block[18..])?;
+            Ok(Some(block))
+        }
+
+        /// Reads and decompresses the next batch of blocks into `buf`
+        fn fill_batch(&mut self) -> io::Result<()> {
+            let mut blocks = Vec::new();
+            while blocks.len() < self.threads * Self::BLOCKS_PER_THREAD {
+                match self.read_block()? {
+                    Some(block) => blocks.push(block),
+                    None => break,
                 }
-            })),
-            None => {
-                Box::new(io::stdout())
-            },
+            }
+
+            let per_thread = blocks.len().div_ceil(self.threads).max(1);
+            let decompressed = std::thread::scope(|scope| {
+                let workers: Vec<_> = blocks.chunks(per_thread).map(|chunk| scope.spawn(move || {
+                    let mut out = Vec::new();
+                    for block in chunk {
+                        flate2::read::GzDecoder::new(&block[..]).read_to_end(&mut out)?;
+                    }
+                    Ok(out)
+                })).collect();
+                workers.into_iter().map(|worker| worker.join().unwrap()).collect::<io::Result<Vec<_>>>()
+            })?;
+
+            self.buf.clear();
+            self.pos = 0;
+            for out in decompressed {
+                self.buf.extend(out);
+            }
+            Ok(())
+        }
+    }
+
+    impl<R: BufRead> Read for BgzfReader<R> {
+        fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+            let n = self.fill_buf()?.read(out)?;
+            self.consume(n);
+            Ok(n)
+        }
+    }
+
+    impl<R: BufRead> BufRead for BgzfReader<R> {
+        fn fill_buf(&mut self) -> io::Result<&[u8]> {
+            // Empty blocks (such as the EOF marker) decompress to nothing, so keep going until data or EOF
+            while self.pos == self.buf.len() {
+                if self.inner.fill_buf()?.is_empty() {
+                    break;
+                }
+                self.fill_batch()?;
+            }
+            Ok(&self.buf[self.pos..])
+        }
+
+        fn consume(&mut self, amt: usize) {
+            self.pos = (self.pos + amt).min(self.buf.len());
         }
     }
+
+    /// Will return writer to File if PathBuf given, appending to it (and creating it if it doesn't exist)
+    /// And return writer to stdout if PathBuf not given
+    pub fn get_writer(output: &Option<PathBuf>) -> io::Result<Box<dyn Write>> {
+        Ok(match output {
+            Some(file) => Box::new(OpenOptions::new().append(true).create(true).open(file)?),
+            None => Box::new(io::stdout()),
+        })
+    }
 }
 
+use kmer_comp::KmerComp;
 use serde::{Serialize, Deserialize};
+use std::ops::AddAssign;
+
+/// How base compositions are rounded to integer percentages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rounding {
+    /// Round each percentage down, so a column may sum to less than 100
+    Truncate,
+    /// Largest remainder rounding, so each column sums to exactly 100
+    LargestRemainder,
+}
+
+/// How characters other than `ATGCN` in reads are counted.
+/// By default, any such character is an error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Alphabet {
+    /// Count lowercase (e.g. soft-masked) bases as their uppercase equivalent
+    pub fold_lowercase: bool,
+    /// Count `U`, as found in RNA reads, as `T`
+    pub u_as_t: bool,
+    /// How IUPAC ambiguity codes (`RYKMSWBDHV`) and `.` placeholders are counted
+    pub ambiguous: AmbiguousBases,
+}
+
+/// How IUPAC ambiguity codes and `.` placeholders are counted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AmbiguousBases {
+    /// Return `Err(Fastq2CompError::InvalidBase)`
+    #[default]
+    Reject,
+    /// Count them as `N`
+    N,
+    /// Count them in the `other` count of their column, separate from `ATGCN`
+    Other,
+}
+
+/// What a character in a read is counted as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Counted {
+    /// One of `ATGCN`
+    Base(u8),
+    /// Counted in `other`
+    Other,
+}
+
+impl Alphabet {
+    /// Returns what `base` is counted as, or `None` if it is rejected.
+    pub(crate) fn count_as (self, base: u8) -> Option<Counted> {
+        let base = if self.fold_lowercase {base.to_ascii_uppercase()} else {base};
+        match base {
+            b'A' | b'T' | b'G' | b'C' | b'N' => Some(Counted::Base(base)),
+            b'U' if self.u_as_t => Some(Counted::Base(b'T')),
+            b'R' | b'Y' | b'K' | b'M' | b'S' | b'W' | b'B' | b'D' | b'H' | b'V' | b'.' => match self.ambiguous {
+                AmbiguousBases::Reject => None,
+                AmbiguousBases::N => Some(Counted::Base(b'N')),
+                AmbiguousBases::Other => Some(Counted::Other),
+            },
+            _ => None,
+        }
+    }
+
+    /// Returns the 0-based position and the character of the first character of `seq` which is rejected, `None` if none are.
+    pub(crate) fn find_rejected (self, seq: &[u8]) -> Option<(usize, u8)> {
+        seq.iter().position(|&c| self.count_as(c).is_none()).map(|i| (i, seq[i]))
+    }
+
+    /// Returns whether `base` is counted as `N`
+    pub fn is_n (self, base: u8) -> bool {
+        self.count_as(base) == Some(Counted::Base(b'N'))
+    }
+}
+
+/// Parses a comma-separated list of `lowercase` (fold lowercase), `u` (U as T),
+/// and `iupac-n` or `iupac-other` (count ambiguity codes as N or as other), e.g. `lowercase,u,iupac-n`.
+/// An empty list is the default, strict alphabet.
+impl std::str::FromStr for Alphabet {
+    type Err = String;
+
+    fn from_str (s: &str) -> Result<Self, Self::Err> {
+        let mut alphabet = Alphabet::default();
+        for option in s.split(',').filter(|option| !option.is_empty()) {
+            match option {
+                "lowercase" => alphabet.fold_lowercase = true,
+                "u" => alphabet.u_as_t = true,
+                "iupac-n" => alphabet.ambiguous = AmbiguousBases::N,
+                "iupac-other" => alphabet.ambiguous = AmbiguousBases::Other,
+                _ => return Err(format!("unknown alphabet option {:?}, expected lowercase, u, iupac-n or iupac-other", option)),
+            }
+        }
+        Ok(alphabet)
+    }
+}
+
+/// Converts counts to integer percentages of their sum, rounded as `rounding` says.
+/// With largest remainder rounding, points lost to rounding down are handed out largest remainders first, ties broken by index.
+/// All 0 counts are left as all 0.
+pub(crate) fn percentages (counts: &[usize], rounding: Rounding) -> Vec<usize> {
+    let sum = counts.iter().sum::<usize>();
+    if sum == 0 {
+        return counts.to_vec();
+    }
+
+    let mut percentages: Vec<usize> = counts.iter().map(|count| (count * 100) / sum).collect();
+    if rounding == Rounding::LargestRemainder {
+        let remainders: Vec<usize> = counts.iter().map(|count| (count * 100) % sum).collect();
+
+        let mut order: Vec<usize> = (0..percentages.len()).collect();
+        order.sort_by(|&a, &b| remainders[b].cmp(&remainders[a]));
+        let lost = 100 - percentages.iter().sum::<usize>();
+        for &i in order.iter().take(lost) {
+            percentages[i] += 1;
+        }
+    }
+
+    percentages
+}
 
 /// Represents a column of base composition data.
-/// Contains base composition along with position information.
+/// Contains raw base counts along with position information,
+/// and the number of reads which had a base at this position.
+/// Percentages and fractions are derived from the counts by [`BaseComp::compute_percentages`].
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 #[allow(non_snake_case)]
 pub struct BaseCompCol {
     pub pos: usize,
+    #[serde(default)]
+    pub coverage: u64,
+    /// Raw count of each base
     pub bases: BaseCompColBases,
+    /// Percentage of each base, `None` if the column has no bases.
+    #[serde(default)]
+    pub percentages: Option<BaseCompColBases>,
+    /// Fractions of each base, `None` if the column has no bases.
+    #[serde(default)]
+    pub fractions: Option<BaseCompFractions>,
+    /// Count of ambiguity codes, if counted separately (see [`AmbiguousBases::Other`]), only output if non-zero.
+    /// Included in `coverage`, but not in `percentages` and `fractions`, which are of `ATGCN` only.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub other: u64,
+    /// Quality profile of the column, only present if qualities were extracted along with the bases.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quals: Option<BaseQualCol>,
 }
 
 /// Represents a column of base composition.
@@ -134,9 +521,158 @@ impl BaseCompColBases {
         BaseCompColBases {A: 0, G: 0, T: 0, C: 0, N: 0}
     }
 
+    /// Converts counts to percentages, rounded down.
+    /// So the percentages may sum to less than 100. Columns with no bases are left as all 0.
     pub fn percentage (&mut self) {
-        let sum = self.iter().sum::<usize>();
-        *self = self.iter().map(|base| (base * 100) / sum).collect();
+        *self = percentages(&self.iter().collect::<Vec<_>>(), Rounding::Truncate).into_iter().collect();
+    }
+
+    /// Converts counts to percentages, using largest remainder rounding so the percentages always sum to 100.
+    /// Columns with no bases are left as all 0.
+    pub fn percentage_rounded (&mut self) {
+        *self = percentages(&self.iter().collect::<Vec<_>>(), Rounding::LargestRemainder).into_iter().collect();
+    }
+
+    /// Returns the fraction of each base in the column, or `None` if the column has no bases.
+    pub fn fractions (&self) -> Option<BaseCompFractions> {
+        let sum = self.iter().sum::<usize>() as f64;
+        if sum == 0.0 {
+            return None;
+        }
+
+        Some(BaseCompFractions {
+            A: self.A as f64 / sum,
+            T: self.T as f64 / sum,
+            G: self.G as f64 / sum,
+            C: self.C as f64 / sum,
+            N: self.N as f64 / sum,
+        })
+    }
+}
+
+/// Represents a column of base composition as fractions of the bases in the column, summing to 1.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+#[allow(non_snake_case)]
+pub struct BaseCompFractions {
+    pub A: f64,
+    pub T: f64,
+    pub G: f64,
+    pub C: f64,
+    pub N: f64,
+}
+
+/// Represents the qualities of a column, as a FastQC-style per-base quality profile.
+/// Holds the count of each Phred score, from which the statistics are derived by [`BaseQualCol::compute_stats`].
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct BaseQualCol {
+    /// Number of bases with each Phred score, indexed by score
+    pub counts: Vec<u64>,
+    /// Number of each base with a quality
+    pub bases: BaseCompColBases,
+    /// Sum of the Phred scores of each base
+    pub sums: BaseCompColBases,
+    /// Number of bases counted as other (see [`AmbiguousBases::Other`]) with a quality, only output if non-zero
+    #[serde(default, skip_serializing_if = "is_zero_usize")]
+    pub other: usize,
+    /// Sum of the Phred scores of bases counted as other, only output if non-zero
+    #[serde(default, skip_serializing_if = "is_zero_usize")]
+    pub other_sum: usize,
+    /// Mean Phred score, `None` if the column has no qualities.
+    #[serde(default)]
+    pub mean: Option<f64>,
+    #[serde(default)]
+    pub lower_quartile: Option<u8>,
+    #[serde(default)]
+    pub median: Option<u8>,
+    #[serde(default)]
+    pub upper_quartile: Option<u8>,
+    /// Mean Phred score of each base
+    #[serde(default)]
+    pub base_means: BaseQualMeans,
+}
+
+/// Mean Phred score of each base, `None` for bases not found in the column.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone, Default)]
+#[allow(non_snake_case)]
+pub struct BaseQualMeans {
+    pub A: Option<f64>,
+    pub T: Option<f64>,
+    pub G: Option<f64>,
+    pub C: Option<f64>,
+    pub N: Option<f64>,
+}
+
+impl BaseQualCol {
+    /// Adds the Phred score of a base.
+    /// Bases counted as other are only part of the overall statistics, not of the per-base means.
+    fn extract (&mut self, base: Counted, phred: u8) {
+        let phred_index = phred as usize;
+        if self.counts.len() <= phred_index {
+            self.counts.resize(phred_index + 1, 0);
+        }
+        self.counts[phred_index] += 1;
+
+        let (count, sum) = match base {
+            Counted::Base(b'A') => (&mut self.bases.A, &mut self.sums.A),
+            Counted::Base(b'T') => (&mut self.bases.T, &mut self.sums.T),
+            Counted::Base(b'G') => (&mut self.bases.G, &mut self.sums.G),
+            Counted::Base(b'C') => (&mut self.bases.C, &mut self.sums.C),
+            Counted::Base(_) => (&mut self.bases.N, &mut self.sums.N),
+            Counted::Other => {
+                self.other_sum += phred as usize;
+                self.other += 1;
+                return;
+            },
+        };
+        *count += 1;
+        *sum += phred as usize;
+    }
+
+    /// Returns the lowest Phred score at or below which lie at least `fraction` of the scores.
+    fn percentile (&self, fraction: f64) -> Option<u8> {
+        let total: u64 = self.counts.iter().sum();
+        let rank = ((total as f64 * fraction).ceil() as u64).max(1);
+
+        let mut seen = 0;
+        for (phred, &count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Some(phred as u8);
+            }
+        }
+        None
+    }
+
+    /// Sets the mean, quartiles and per-base means from the counts.
+    pub fn compute_stats (&mut self) {
+        let mean = |sum: usize, count: usize| if count == 0 {None} else {Some(sum as f64 / count as f64)};
+
+        self.mean = mean(self.sums.iter().sum::<usize>() + self.other_sum, self.bases.iter().sum::<usize>() + self.other);
+        self.lower_quartile = self.percentile(0.25);
+        self.median = self.percentile(0.5);
+        self.upper_quartile = self.percentile(0.75);
+        self.base_means = BaseQualMeans {
+            A: mean(self.sums.A, self.bases.A),
+            T: mean(self.sums.T, self.bases.T),
+            G: mean(self.sums.G, self.bases.G),
+            C: mean(self.sums.C, self.bases.C),
+            N: mean(self.sums.N, self.bases.N),
+        };
+    }
+}
+
+impl AddAssign<&BaseQualCol> for BaseQualCol {
+    fn add_assign(&mut self, other: &BaseQualCol) {
+        if self.counts.len() < other.counts.len() {
+            self.counts.resize(other.counts.len(), 0);
+        }
+        for (count, other) in self.counts.iter_mut().zip(other.counts.iter()) {
+            *count += other;
+        }
+        self.bases += other.bases;
+        self.sums += other.sums;
+        self.other += other.other;
+        self.other_sum += other.other_sum;
     }
 }
 
@@ -146,6 +682,16 @@ impl Default for BaseCompColBases {
     }
 }
 
+impl AddAssign for BaseCompColBases {
+    fn add_assign(&mut self, other: Self) {
+        self.A += other.A;
+        self.T += other.T;
+        self.G += other.G;
+        self.C += other.C;
+        self.N += other.N;
+    }
+}
+
 use std::iter::FromIterator;
 impl FromIterator<usize> for BaseCompColBases {
     fn from_iter<I: IntoIterator<Item=usize>>(iter: I) -> Self {
@@ -178,7 +724,7 @@ mod col_base_comp_tests {
     fn test_iterability() {
         // test if conversion TO iterator works
         let mut read = BaseCompCol::new(0);
-        read.extract(&b'A');
+        read.extract(&b'A').unwrap();
 
         let mut iter = read.bases.iter();
         assert_eq!(iter.next().unwrap(), 1);
@@ -186,7 +732,7 @@ mod col_base_comp_tests {
 
         // test if converstion FROM iterator works
         let mut read = BaseCompCol::new(0);
-        read.extract(&b'A');
+        read.extract(&b'A').unwrap();
 
         let iter = read.bases.iter();
         let converted_read: BaseCompColBases = iter.collect();
@@ -197,26 +743,26 @@ mod col_base_comp_tests {
     #[test]
     fn test_extract() {
         let mut read = BaseCompCol::new(0);
-        read.extract(&b'A');
+        read.extract(&b'A').unwrap();
         assert_eq!(read.bases.A, 1);
 
-        read.extract(&b'C');
+        read.extract(&b'C').unwrap();
         assert_eq!(read.bases.C, 1);
 
-        read.extract(&b'T');
+        read.extract(&b'T').unwrap();
         assert_eq!(read.bases.T, 1);
 
-        read.extract(&b'G');
+        read.extract(&b'G').unwrap();
         assert_eq!(read.bases.G, 1);
 
-        read.extract(&b'N');
+        read.extract(&b'N').unwrap();
         assert_eq!(read.bases.N, 1);
     }
     #[test]
     fn test_percentage() {
         let mut read = BaseCompCol::new(0);
         for c in "ACTGN".as_bytes().iter() {
-            read.extract(c);
+            read.extract(c).unwrap();
         }
         read.bases.percentage();
 
@@ -228,39 +774,452 @@ mod col_base_comp_tests {
         assert_eq!(read.bases.G, 20, "Testing G");
         assert_eq!(read.bases.N, 20, "Testing N");
     }
+
+    #[test]
+    fn test_percentage_rounded() {
+        // 2/7, 4/7, 1/7 would be rounded down to 28, 57, 14
+        let mut bases = BaseCompColBases {A: 2, T: 4, G: 0, C: 1, N: 0};
+        bases.percentage_rounded();
+        assert_eq!(bases, BaseCompColBases {A: 29, T: 57, G: 0, C: 14, N: 0});
+
+        // 1/3 each, ties broken in order
+        let mut bases = BaseCompColBases {A: 1, T: 0, G: 1, C: 1, N: 0};
+        bases.percentage_rounded();
+        assert_eq!(bases, BaseCompColBases {A: 34, T: 0, G: 33, C: 33, N: 0});
+
+        for (a, t, g, c, n) in [(7, 1, 0, 0, 0), (1, 1, 1, 1, 3), (13, 29, 31, 17, 1), (0, 0, 0, 0, 1)].iter() {
+            let mut bases = BaseCompColBases {A: *a, T: *t, G: *g, C: *c, N: *n};
+            bases.percentage_rounded();
+            assert_eq!(bases.iter().sum::<usize>(), 100, "{:?}", bases);
+        }
+    }
+
+    #[test]
+    fn test_empty_column() {
+        let mut bases = BaseCompColBases::new();
+        assert_eq!(bases.fractions(), None);
+
+        bases.percentage();
+        assert_eq!(bases, BaseCompColBases::new());
+        bases.percentage_rounded();
+        assert_eq!(bases, BaseCompColBases::new());
+
+        // a column of only ambiguity codes has neither percentages nor fractions, output as null
+        let mut comp = BaseComp::with_alphabet(0, "iupac-other".parse().unwrap());
+        comp.extract(b"AR").unwrap();
+        comp.compute_percentages(Rounding::LargestRemainder);
+        assert_eq!((comp.lib[1].percentages, comp.lib[1].fractions), (None, None));
+        let json = serde_json::to_string(&comp.lib[1]).unwrap();
+        assert!(json.contains("\"percentages\":null,\"fractions\":null"), "{}", json);
+        assert_eq!(serde_json::from_str::<BaseCompCol>(&json).unwrap(), comp.lib[1]);
+    }
+
+    #[test]
+    fn test_fractions() {
+        let bases = BaseCompColBases {A: 7, T: 1, G: 0, C: 0, N: 0};
+        assert_eq!(bases.fractions(), Some(BaseCompFractions {A: 0.875, T: 0.125, G: 0.0, C: 0.0, N: 0.0}));
+    }
+
+    #[test]
+    fn test_variable_length() {
+        let mut comp = BaseComp::init(2);
+        for read in [&b"ACGT"[..], b"AC", b"ACGTAA", b""].iter() {
+            comp.extract(read).unwrap();
+        }
+
+        assert_eq!(comp.len(), 6);
+        assert_eq!(comp.reads_read(), 4);
+        assert_eq!(comp.lib.iter().map(|c| c.coverage).collect::<Vec<_>>(), vec![3, 3, 2, 2, 1, 1]);
+        assert_eq!(comp.lib.iter().map(|c| c.pos).collect::<Vec<_>>(), vec![1, 2, 3, 4, 5, 6]);
+
+        comp.compute_percentages(Rounding::Truncate);
+        assert_eq!(comp.lib[1].percentages, Some(BaseCompColBases {A: 0, T: 0, G: 0, C: 100, N: 0}));
+        assert_eq!(comp.lib[3].percentages, Some(BaseCompColBases {A: 0, T: 100, G: 0, C: 0, N: 0}));
+        assert_eq!(comp.lib[4].percentages, Some(BaseCompColBases {A: 100, T: 0, G: 0, C: 0, N: 0}));
+        // counts are kept
+        assert_eq!(comp.lib[4].bases, BaseCompColBases {A: 1, T: 0, G: 0, C: 0, N: 0});
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut a = BaseComp::init(0);
+        a.extract(b"ACG").unwrap();
+        a.extract(b"AAG").unwrap();
+
+        let mut b = BaseComp::init(0);
+        b.extract(b"TCGTA").unwrap();
+
+        a.merge(&b).unwrap();
+        assert_eq!(a.len(), 5);
+        assert_eq!(a.reads_read(), 3);
+        assert_eq!(a.lib.iter().map(|c| c.coverage).collect::<Vec<_>>(), vec![3, 3, 3, 1, 1]);
+        assert_eq!(a.lib[0].bases, BaseCompColBases {A: 2, T: 1, G: 0, C: 0, N: 0});
+        assert_eq!(a.lib[3].bases, BaseCompColBases {A: 0, T: 1, G: 0, C: 0, N: 0});
+
+        // merging is the same as extracting all reads into a single composition
+        let mut c = BaseComp::init(0);
+        for read in [&b"TCGTA"[..], b"ACG", b"AAG"].iter() {
+            c.extract(read).unwrap();
+        }
+        c.compute_percentages(Rounding::Truncate);
+        a.compute_percentages(Rounding::Truncate);
+        assert_eq!(a.lib, c.lib);
+    }
+
+    #[test]
+    fn test_quality_profile() {
+        let mut comp = BaseComp::init(0);
+        comp.extract_with_phreds(b"ACG", &[30, 2, 40]).unwrap();
+        comp.extract_with_phreds(b"AGGT", &[10, 20, 40, 35]).unwrap();
+        comp.extract_with_phreds(b"TC", &[40, 40]).unwrap();
+        // bases without qualities aren't part of the profile
+        comp.extract_with_phreds(b"AAAAA", &[]).unwrap();
+        comp.compute_percentages(Rounding::Truncate);
+
+        let quals = comp.lib[0].quals.as_ref().unwrap();
+        assert_eq!(quals.bases, BaseCompColBases {A: 2, T: 1, G: 0, C: 0, N: 0});
+        assert_eq!(quals.mean, Some(80.0 / 3.0));
+        assert_eq!((quals.lower_quartile, quals.median, quals.upper_quartile), (Some(10), Some(30), Some(40)));
+        assert_eq!(quals.base_means, BaseQualMeans {A: Some(20.0), T: Some(40.0), G: None, C: None, N: None});
+
+        let quals = comp.lib[3].quals.as_ref().unwrap();
+        assert_eq!((quals.lower_quartile, quals.median, quals.upper_quartile), (Some(35), Some(35), Some(35)));
+        assert_eq!(comp.lib[4].quals, None);
+
+        // merging is the same as extracting all reads into a single composition
+        let mut a = BaseComp::init(0);
+        a.extract_with_phreds(b"ACG", &[30, 2, 40]).unwrap();
+        let mut b = BaseComp::init(0);
+        b.extract_with_phreds(b"AGGT", &[10, 20, 40, 35]).unwrap();
+        b.extract_with_phreds(b"TC", &[40, 40]).unwrap();
+        b.extract(b"AAAAA").unwrap();
+        a.merge(&b).unwrap();
+        a.compute_percentages(Rounding::Truncate);
+        assert_eq!(a.lib, comp.lib);
+    }
+
+    #[test]
+    fn test_alphabet() {
+        let strict = Alphabet::default();
+        assert_eq!(strict.count_as(b'G'), Some(Counted::Base(b'G')));
+        for base in b"gUR.X".iter() {
+            assert_eq!(strict.count_as(*base), None);
+        }
+
+        let alphabet: Alphabet = "lowercase,u,iupac-other".parse().unwrap();
+        assert_eq!(alphabet, Alphabet {fold_lowercase: true, u_as_t: true, ambiguous: AmbiguousBases::Other});
+        assert_eq!(alphabet.count_as(b'g'), Some(Counted::Base(b'G')));
+        assert_eq!(alphabet.count_as(b'u'), Some(Counted::Base(b'T')));
+        assert_eq!(alphabet.count_as(b'y'), Some(Counted::Other));
+        assert_eq!(alphabet.count_as(b'X'), None);
+        assert!("lowercase,dna".parse::<Alphabet>().is_err());
+        assert_eq!("".parse::<Alphabet>(), Ok(strict));
+
+        let mut comp = BaseComp::with_alphabet(0, alphabet);
+        comp.extract_with_phreds(b"aU.", &[10, 20, 30]).unwrap();
+        comp.extract(b"ARN").unwrap();
+        assert_eq!(comp.lib[0].bases, BaseCompColBases {A: 2, T: 0, G: 0, C: 0, N: 0});
+        assert_eq!(comp.lib[1].bases, BaseCompColBases {A: 0, T: 1, G: 0, C: 0, N: 0});
+        assert_eq!((comp.lib[1].other, comp.lib[1].coverage), (1, 2));
+        assert_eq!((comp.lib[2].other, comp.lib[2].bases.N), (1, 1));
+
+        // other bases aren't part of the percentages, but are of the quality profile
+        comp.compute_percentages(Rounding::Truncate);
+        assert_eq!(comp.lib[1].percentages.unwrap().T, 100);
+        let quals = comp.lib[2].quals.as_ref().unwrap();
+        assert_eq!((quals.mean, quals.base_means.N), (Some(30.0), None));
+
+        let mut merged = BaseComp::init(0);
+        merged.merge(&comp).unwrap();
+        assert_eq!(merged.lib[1].other, 1);
+
+        let mut comp = BaseComp::with_alphabet(0, Alphabet {ambiguous: AmbiguousBases::N, ..strict});
+        comp.extract(b"R.").unwrap();
+        assert_eq!(comp.lib.iter().map(|col| col.bases.N).sum::<usize>(), 2);
+        assert!(matches!(comp.extract(b"Aa"), Err(Fastq2CompError::InvalidBase {base: 'a', pos: 2, record: 0})));
+    }
+
+    #[test]
+    fn test_gc_content() {
+        let mut comp = BaseComp::init(0);
+        for read in [&b"GGCC"[..], b"ATGC", b"ATGN", b"NNN", b"AT"].iter() {
+            comp.extract(read).unwrap();
+        }
+        let counts = &comp.gc_content().counts;
+        assert_eq!((counts[100], counts[50], counts[33], counts[0]), (1, 1, 1, 1));
+        assert_eq!(counts.iter().sum::<u64>(), 4);
+
+        // merged like the base counts
+        let mut merged = BaseComp::init(0);
+        merged.merge(&comp).unwrap();
+        merged.merge(&comp).unwrap();
+        assert_eq!(merged.gc_content().counts[50], 2);
+
+        // histograms read from JSON must have a bin for each percentage
+        let json = serde_json::to_string(&merged.gc_content).unwrap();
+        assert_eq!(serde_json::from_str::<GcContent>(&json).unwrap(), merged.gc_content);
+        assert!(serde_json::from_str::<GcContent>(&json.replacen("0,", "", 1)).is_err());
+        assert!(serde_json::from_str::<GcContent>("{\"counts\":[1,2,3],\"fit\":null}").is_err());
+
+        // a single read, or reads all of the same GC content, can't be fitted
+        let mut gc = GcContent::default();
+        gc.counts[40] = 10;
+        gc.compute_fit();
+        assert_eq!(gc.fit, None);
+
+        // normally distributed GC content fits well
+        let normal = |i: usize, mean: f64, sd: f64| (-(i as f64 - mean).powi(2) / (2.0 * sd * sd)).exp() / (sd * (2.0 * std::f64::consts::PI).sqrt());
+        let mut gc = GcContent::default();
+        for (i, count) in gc.counts.iter_mut().enumerate() {
+            *count = (normal(i, 45.0, 6.0) * 10000.0).round() as u64;
+        }
+        gc.compute_fit();
+        let fit = gc.fit.unwrap();
+        assert!((fit.mean - 45.0).abs() < 0.5 && (fit.sd - 6.0).abs() < 0.1, "{:?}", fit);
+        assert!(fit.deviation < 5.0, "{:?}", fit);
+
+        // contamination with a GC-rich genome deviates from the fit
+        for (i, count) in gc.counts.iter_mut().enumerate() {
+            *count += (normal(i, 65.0, 4.0) * 6000.0).round() as u64;
+        }
+        gc.compute_fit();
+        let fit = gc.fit.unwrap();
+        assert!((fit.mean - 45.0).abs() < 1.0 && fit.deviation > 30.0, "{:?}", fit);
+    }
+
+    #[test]
+    fn test_kmer_comp() {
+        let mut comp = BaseComp::with_alphabet(0, "lowercase".parse().unwrap());
+        assert!(comp.kmer_comp().is_none());
+        comp.count_kmers(2);
+        comp.extract(b"acgt").unwrap();
+        comp.extract(b"ACG").unwrap();
+        let kmers = comp.kmer_comp().unwrap();
+        assert_eq!((kmers.k(), kmers.len(), kmers.reads_read()), (2, 4, 2));
+        assert_eq!(kmers.lib[1].count(b"CG"), 2);
+
+        // merged into compositions without k-mers, e.g. those of threads
+        let mut merged = BaseComp::init(0);
+        merged.merge(&comp).unwrap();
+        merged.merge(&comp).unwrap();
+        merged.compute_percentages(Rounding::Truncate);
+        let kmers = merged.kmer_comp().unwrap();
+        assert_eq!(kmers.lib[1].count(b"CG"), 4);
+        assert_eq!((kmers.lib[2].count(b"GT"), kmers.lib[2].percentages.as_ref().unwrap().iter().max()), (2, Some(&100)));
+
+        // k-mers of differing lengths aren't merged, and neither is anything else
+        let mut other = BaseComp::init(0);
+        other.count_kmers(3);
+        other.extract(b"ACGT").unwrap();
+        assert!(matches!(merged.merge(&other), Err(Fastq2CompError::MismatchedKmerLength {k: Some(2), other: Some(3)})));
+        assert_eq!((merged.reads_read(), merged.kmer_comp().unwrap().reads_read()), (4, 4));
+    }
+
+    #[test]
+    fn test_invalid_base() {
+        let mut read = BaseCompCol::new(3);
+        assert!(matches!(read.extract(&b'X'), Err(Fastq2CompError::InvalidBase {base: 'X', pos: 3, record: 0})));
+        assert_eq!(read.bases, BaseCompColBases::new());
+
+        // reads with an invalid base are rejected as a whole
+        let mut comp = BaseComp::init(0);
+        comp.extract(b"AC").unwrap();
+        assert!(matches!(comp.extract(b"ACXT"), Err(Fastq2CompError::InvalidBase {base: 'X', pos: 3, record: 0})));
+        assert_eq!((comp.len(), comp.reads_read(), comp.lib[0].bases.A), (2, 1, 1));
+        assert_eq!(comp.gc_content().counts.iter().sum::<u64>(), 1);
+    }
 }
 
 impl BaseCompCol {
     pub fn new (pos: usize) -> BaseCompCol {
-        BaseCompCol {pos, bases: BaseCompColBases {A: 0, T: 0, G: 0, C: 0, N: 0}}
+        BaseCompCol {pos, coverage: 0, bases: BaseCompColBases::new(), percentages: None, fractions: None, other: 0, quals: None}
+    }
+
+    /// Sets `percentages` and `fractions` from the current base counts, and the statistics of the quality profile if any.
+    pub fn compute_percentages (&mut self, rounding: Rounding) {
+        self.fractions = self.bases.fractions();
+        self.percentages = self.fractions.map(|_| {
+            let mut percentages = self.bases;
+            match rounding {
+                Rounding::Truncate => percentages.percentage(),
+                Rounding::LargestRemainder => percentages.percentage_rounded(),
+            }
+            percentages
+        });
+        if let Some(quals) = &mut self.quals {
+            quals.compute_stats();
+        }
+    }
+
+    pub fn extract (&mut self, s: &u8) -> Result<(), Fastq2CompError> {
+        self.extract_in(*s, Alphabet::default()).map(|_| ())
+    }
+
+    /// Counts a base as `alphabet` says, returning what it was counted as.
+    fn extract_in (&mut self, s: u8, alphabet: Alphabet) -> Result<Counted, Fastq2CompError> {
+        let counted = alphabet.count_as(s).ok_or(Fastq2CompError::InvalidBase {base: s as char, pos: self.pos, record: 0})?;
+        match counted {
+            Counted::Base(b'A') => self.bases.A += 1,
+            Counted::Base(b'T') => self.bases.T += 1,
+            Counted::Base(b'G') => self.bases.G += 1,
+            Counted::Base(b'C') => self.bases.C += 1,
+            Counted::Base(_) => self.bases.N += 1,
+            Counted::Other => self.other += 1,
+        }
+        self.coverage += 1;
+        Ok(counted)
+    }
+}
+
+/// Distribution of the GC content of reads, as a histogram of the percentage of called bases (`ATGC`) which are G or C.
+/// A normal distribution fitted to it is set by [`GcContent::compute_fit`], as in FastQC's per sequence GC content module.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(try_from = "GcContentData")]
+pub struct GcContent {
+    /// Number of reads of each GC percentage, 0 to 100. Reads without called bases aren't counted.
+    pub counts: Vec<u64>,
+    /// Normal distribution fitted to `counts`, `None` if there are too few reads or all have the same GC content.
+    #[serde(default)]
+    pub fit: Option<NormalFit>,
+}
+
+/// Normal distribution fitted to a [`GcContent`] histogram
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub struct NormalFit {
+    pub mean: f64,
+    pub sd: f64,
+    /// Percentage of reads by which the histogram deviates from the fit, the sum of absolute differences of each bin.
+    /// FastQC warns above 15, and fails above 30.
+    pub deviation: f64,
+}
+
+/// [`GcContent`] as read from JSON, before checking that it has a bin for each percentage
+#[derive(Deserialize)]
+struct GcContentData {
+    counts: Vec<u64>,
+    #[serde(default)]
+    fit: Option<NormalFit>,
+}
+
+impl std::convert::TryFrom<GcContentData> for GcContent {
+    type Error = String;
+
+    fn try_from (data: GcContentData) -> Result<Self, Self::Error> {
+        match data.counts.len() {
+            101 => Ok(GcContent {counts: data.counts, fit: data.fit}),
+            len => Err(format!("GC content has {} bins, expected 101 (0 to 100%)", len)),
+        }
     }
+}
+
+impl Default for GcContent {
+    fn default() -> Self {
+        GcContent {counts: vec![0; 101], fit: None}
+    }
+}
+
+impl GcContent {
+    /// Adds a read with `gc` G or C bases out of `called` bases.
+    fn extract (&mut self, gc: usize, called: usize) {
+        if called == 0 {
+            return;
+        }
+        let percent = (gc as f64 * 100.0 / called as f64).round() as usize;
+        self.counts[percent] += 1;
+    }
+
+    /// Fits a normal distribution to the histogram.
+    /// Like FastQC, its mean is the mode of the histogram (averaged over the bins around it within 90% of its count),
+    /// so it isn't skewed by contamination.
+    pub fn compute_fit (&mut self) {
+        let total: u64 = self.counts.iter().sum();
+        let (mode, &mode_count) = match self.counts.iter().enumerate().rev().max_by_key(|&(_, count)| count) {
+            Some(max) if total > 1 => max,
+            _ => {
+                self.fit = None;
+                return;
+            },
+        };
+
+        let near_mode = |i: &usize| self.counts[*i] as f64 >= mode_count as f64 * 0.9;
+        let above = (mode + 1..self.counts.len()).take_while(near_mode);
+        let below = (0..mode).rev().take_while(near_mode);
+        let bins: Vec<usize> = std::iter::once(mode).chain(above).chain(below).collect();
+        let mean = bins.iter().sum::<usize>() as f64 / bins.len() as f64;
+
+        let variance = self.counts.iter().enumerate()
+            .map(|(i, &count)| (i as f64 - mean).powi(2) * count as f64)
+            .sum::<f64>() / (total - 1) as f64;
+        let sd = variance.sqrt();
+        if sd == 0.0 {
+            self.fit = None;
+            return;
+        }
+
+        let deviation = self.counts.iter().enumerate().map(|(i, &count)| {
+            let expected = (-(i as f64 - mean).powi(2) / (2.0 * variance)).exp() / (sd * (2.0 * std::f64::consts::PI).sqrt()) * total as f64;
+            (count as f64 - expected).abs()
+        }).sum::<f64>() / total as f64 * 100.0;
 
-    pub fn extract (&mut self, s: &u8) {
-        match s {
-            b'A' => self.bases.A += 1,
-            b'T' => self.bases.T += 1,
-            b'G' => self.bases.G += 1,
-            b'C' => self.bases.C += 1,
-            b'N' => self.bases.N += 1,
-            _ => panic!("Invalid character {:?} == {:?} found in read", *s as char, s.to_ascii_lowercase())
-        }            
+        self.fit = Some(NormalFit {mean, sd, deviation});
     }
+}
 
-    
+impl AddAssign<&GcContent> for GcContent {
+    fn add_assign(&mut self, other: &GcContent) {
+        for (count, other) in self.counts.iter_mut().zip(other.counts.iter()) {
+            *count += other;
+        }
+    }
 }
 
 /// Represents the entire base composition.
 /// As a Vec of `BaseCompCol`(umns), each of which hold data for a single column.
+/// Columns are added as longer reads are extracted, so reads may be of differing lengths.
 /// Also holds data on how many reads were read to produce the compositions.
 #[derive(Serialize, Deserialize, Debug)]
 pub struct BaseComp {
     pub lib: Vec<BaseCompCol>,
+    /// GC content of the reads the base compositions were extracted from.
+    #[serde(default)]
+    gc_content: GcContent,
+    /// k-mer composition of the reads, only counted if enabled with [`BaseComp::count_kmers`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    kmers: Option<KmerComp>,
+    /// Reads the base compositions were extracted from.
     reads_read: u64,
+    /// Reads in the input which passed all checks, of which `reads_read` were sampled.
+    #[serde(default)]
+    reads_accepted: u64,
+
+    /// Malformed records skipped while reading input, only output if non-zero.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    records_skipped: u64,
+
+    /// How bases are counted by [`BaseComp::extract`], recorded in the output as part of the sample arguments.
+    #[serde(skip)]
+    alphabet: Alphabet,
+    /// Encoding qualities of input were read in, given or detected, recorded in the output as part of the sample arguments.
+    #[serde(skip)]
+    quality_encoding: Option<extract_comp::QualityEncoding>,
+}
+
+fn is_zero(n: &u64) -> bool {
+    *n == 0
+}
+
+fn is_zero_usize(n: &usize) -> bool {
+    *n == 0
 }
 
 impl BaseComp {
     pub fn init (len: usize) -> BaseComp {
-        let mut base_comp = BaseComp { lib: Vec::with_capacity(len), reads_read: 0};
+        BaseComp::with_alphabet(len, Alphabet::default())
+    }
+
+    /// Like [`BaseComp::init`], counting bases other than `ATGCN` as `alphabet` says.
+    pub fn with_alphabet (len: usize, alphabet: Alphabet) -> BaseComp {
+        let mut base_comp = BaseComp { lib: Vec::with_capacity(len), gc_content: GcContent::default(), kmers: None, reads_read: 0, reads_accepted: 0, records_skipped: 0, alphabet, quality_encoding: None};
         for i in 1..=len {
             base_comp.lib.push(BaseCompCol::new(i));
         }
@@ -272,6 +1231,41 @@ impl BaseComp {
         self.reads_read
     }
 
+    pub fn reads_accepted (&self) -> u64 {
+        self.reads_accepted
+    }
+
+    pub fn records_skipped (&self) -> u64 {
+        self.records_skipped
+    }
+
+    /// Encoding the qualities of input were read in, if extracted by [`extract_comp::run`] from input with qualities.
+    /// Unlike `SampleArgs::quality_encoding`, this is set if the encoding was detected.
+    pub fn quality_encoding (&self) -> Option<extract_comp::QualityEncoding> {
+        self.quality_encoding
+    }
+
+    pub fn gc_content (&self) -> &GcContent {
+        &self.gc_content
+    }
+
+    pub fn kmer_comp (&self) -> Option<&KmerComp> {
+        self.kmers.as_ref()
+    }
+
+    /// Also counts k-mers of length `k` in reads extracted from now on, see [`KmerComp`].
+    ///
+    /// Panics if `k` is 0 or greater than [`kmer_comp::MAX_K`].
+    pub fn count_kmers (&mut self, k: usize) {
+        self.kmers = Some(KmerComp::new(k, self.alphabet));
+    }
+
+    /// Reads base compositions from JSON output (see [`extract_comp::Output`]).
+    /// Returns `Err(Fastq2CompError::IncompatibleSchema)` if the output was written using a different schema version.
+    pub fn from_json (json: &str) -> Result<BaseComp, Fastq2CompError> {
+        Ok(extract_comp::Output::from_json(json)?.comp)
+    }
+
     pub fn len (&self) -> usize {
         self.lib.len()
     }
@@ -280,10 +1274,98 @@ impl BaseComp {
         self.len() == 0
     }
 
-    pub fn extract (&mut self, s: &str) {
-        for c in s.as_bytes().iter().enumerate() {
-            self.lib[c.0].extract(c.1);
+    /// Sets the percentages and fractions of every column from their base counts, and fits the GC content distribution.
+    /// Base counts are left as is, so more reads can still be extracted (percentages then need to be recomputed).
+    pub fn compute_percentages (&mut self, rounding: Rounding) {
+        for col in self.lib.iter_mut() {
+            col.compute_percentages(rounding);
+        }
+        self.gc_content.compute_fit();
+        if let Some(kmers) = &mut self.kmers {
+            kmers.compute_percentages(rounding);
+        }
+    }
+
+    /// Adds the base counts of `other` to this composition, e.g. to combine the results of several lanes or files.
+    /// Columns are matched by position, and compositions of differing lengths are merged up to the longer length.
+    /// Quality profiles and k-mer compositions are merged too, so if only one composition has them they cover only its reads.
+    /// Percentages need to be recomputed afterwards using [`BaseComp::compute_percentages`].
+    ///
+    /// Returns `Err(Fastq2CompError::MismatchedKmerLength)` if both have k-mer compositions of differing k-mer lengths,
+    /// and `Err(Fastq2CompError::MismatchedSampleArgs)` if both have k-mer compositions read with differing alphabets,
+    /// in which case nothing is merged.
+    pub fn merge (&mut self, other: &BaseComp) -> Result<(), Fastq2CompError> {
+        self.check_merge(other)?;
+        for pos in self.lib.len() + 1..=other.lib.len() {
+            self.lib.push(BaseCompCol::new(pos));
+        }
+        for (col, other) in self.lib.iter_mut().zip(other.lib.iter()) {
+            col.coverage += other.coverage;
+            col.bases += other.bases;
+            col.other += other.other;
+            if let Some(other_quals) = &other.quals {
+                *col.quals.get_or_insert_with(BaseQualCol::default) += other_quals;
+            }
+        }
+
+        self.gc_content += &other.gc_content;
+        match (&mut self.kmers, &other.kmers) {
+            (Some(kmers), Some(other_kmers)) => kmers.merge(other_kmers)?,
+            (None, Some(other_kmers)) => self.kmers = Some(other_kmers.clone()),
+            _ => {},
+        }
+        self.reads_read += other.reads_read;
+        self.reads_accepted += other.reads_accepted;
+        self.records_skipped += other.records_skipped;
+        Ok(())
+    }
+
+    /// Returns `Err` if the k-mer compositions of `other` can't be merged into this one's, see [`KmerComp::check_merge`].
+    pub(crate) fn check_merge (&self, other: &BaseComp) -> Result<(), Fastq2CompError> {
+        match (&self.kmers, &other.kmers) {
+            (Some(kmers), Some(other_kmers)) => kmers.check_merge(other_kmers),
+            _ => Ok(()),
+        }
+    }
+
+    /// Adds the bases of a read to the composition.
+    /// Reads longer than the composition add new columns, reads shorter than it only count towards the columns they cover.
+    /// Returns `Err(Fastq2CompError::InvalidBase)` if the read has a character rejected by the alphabet, leaving the composition unchanged.
+    pub fn extract (&mut self, s: &[u8]) -> Result<(), Fastq2CompError> {
+        if let Some((i, base)) = self.alphabet.find_rejected(s) {
+            return Err(Fastq2CompError::InvalidBase {base: base as char, pos: i + 1, record: 0});
+        }
+
+        for pos in self.lib.len() + 1..=s.len() {
+            self.lib.push(BaseCompCol::new(pos));
+        }
+
+        let (mut gc, mut called) = (0, 0);
+        for c in s.iter().enumerate() {
+            match self.lib[c.0].extract_in(*c.1, self.alphabet)? {
+                Counted::Base(b'G') | Counted::Base(b'C') => {gc += 1; called += 1},
+                Counted::Base(b'A') | Counted::Base(b'T') => called += 1,
+                _ => {},
+            }
+        }
+        self.gc_content.extract(gc, called);
+        if let Some(kmers) = &mut self.kmers {
+            kmers.extract(s);
         }
         self.reads_read += 1;
+        Ok(())
+    }
+
+    /// Adds the bases of a read to the composition as [`BaseComp::extract`] does,
+    /// and their Phred scores to the quality profiles of the columns.
+    /// `phreds` may be shorter than the read (e.g. empty for a read without qualities), in which case only the bases it covers get a quality.
+    pub fn extract_with_phreds (&mut self, s: &[u8], phreds: &[u8]) -> Result<(), Fastq2CompError> {
+        self.extract(s)?;
+        for ((col, &base), &phred) in self.lib.iter_mut().zip(s).zip(phreds) {
+            // Bases were checked by extract
+            let base = self.alphabet.count_as(base).unwrap();
+            col.quals.get_or_insert_with(BaseQualCol::default).extract(base, phred);
+        }
+        Ok(())
     }
 }
\ No newline at end of file
@@ -0,0 +1,527 @@
+use std::io::{self, BufRead, Read};
+use crate::Fastq2CompError;
+use crate::extract_comp::{FASTQReader, ParseMode, QualityEncoding, RecordSource, SampleArgs};
+
+#[cfg(test)]
+mod test_sam {
+    use super::*;
+    use crate::test_utils::*;
+
+    const SAM: &[u8] = b"@HD\tVN:1.6\tSO:unsorted
+@RG\tID:A
+r1\t4\t*\t0\t0\t*\t*\t0\t0\tACGTN\tIIII#\tRG:Z:A
+r2\t20\t*\t0\t0\t*\t*\t0\t0\tAACGT\t+,-./
+r3\t256\t*\t0\t0\t*\t*\t0\t0\tGGGGG\tIIIII
+r4\t2052\t*\t0\t0\t*\t*\t0\t0\tGGGGG\tIIIII
+r5\t4\t*\t0\t0\t*\t*\t0\t0\tTTTTT\t*
+";
+
+    /// Encodes an unmapped BAM record
+    fn bam_record(name: &[u8], flag: u16, seq: &[u8], quals: Option<&[u8]>) -> Vec<u8> {
+        let mut record = Vec::new();
+        record.extend_from_slice(&(-1i32).to_le_bytes()); // refID
+        record.extend_from_slice(&(-1i32).to_le_bytes()); // pos
+        record.push(name.len() as u8 + 1);
+        record.push(255); // mapq
+        record.extend_from_slice(&4680u16.to_le_bytes()); // bin
+        record.extend_from_slice(&0u16.to_le_bytes()); // n_cigar_op
+        record.extend_from_slice(&flag.to_le_bytes());
+        record.extend_from_slice(&(seq.len() as u32).to_le_bytes());
+        record.extend_from_slice(&(-1i32).to_le_bytes()); // next_refID
+        record.extend_from_slice(&(-1i32).to_le_bytes()); // next_pos
+        record.extend_from_slice(&0i32.to_le_bytes()); // tlen
+        record.extend_from_slice(name);
+        record.push(0);
+        for pair in seq.chunks(2) {
+            let code = |b: &u8| BAM_BASES.iter().position(|c| c == b).unwrap() as u8;
+            record.push(code(&pair[0]) << 4 | pair.get(1).map_or(0, code));
+        }
+        match quals {
+            Some(quals) => record.extend(quals.iter().map(|q| q - 33)),
+            None => record.extend(seq.iter().map(|_| 0xff)),
+        }
+
+        let mut block = (record.len() as i32).to_le_bytes().to_vec();
+        block.extend(record);
+        block
+    }
+
+    fn bam(records: &[Vec<u8>]) -> Vec<u8> {
+        let text = b"@HD\tVN:1.6\n";
+        let mut bam = b"BAM\x01".to_vec();
+        bam.extend_from_slice(&(text.len() as i32).to_le_bytes());
+        bam.extend_from_slice(text);
+        bam.extend_from_slice(&1i32.to_le_bytes()); // n_ref
+        bam.extend_from_slice(&5i32.to_le_bytes());
+        bam.extend_from_slice(b"chr1\0");
+        bam.extend_from_slice(&1000i32.to_le_bytes());
+        for record in records {
+            bam.extend_from_slice(record);
+        }
+        bam
+    }
+
+    #[test]
+    fn test_read_sam() {
+        let mut read = SAMRead::sam(5);
+        let mut reader = return_reader(SAM);
+        let mut reads = Vec::new();
+        while read.read_record(&mut reader).unwrap().is_some() {
+            reads.push((read.seq().to_vec(), read.quals().map(<[u8]>::to_vec), read.record()));
+        }
+
+        // secondary and supplementary records are skipped, reverse strand reads are reverse complemented
+        assert_eq!(reads, vec![
+            (b"ACGTN".to_vec(), Some(b"IIII#".to_vec()), 1),
+            (b"ACGTT".to_vec(), Some(b"/.-,+".to_vec()), 2),
+            (b"TTTTT".to_vec(), None, 5),
+        ]);
+    }
+
+    #[test]
+    fn test_malformed_sam() {
+        let input = b"r1\t4\t*\t0\t0\t*\t*\t0\t0\tACGTN\n\nr2\tx\t*\t0\t0\t*\t*\t0\t0\tACGTN\tIIIII\nr3\t4\t*\t0\t0\t*\t*\t0\t0\tACGTN\tIIII\n";
+
+        let mut read = SAMRead::sam(5);
+        assert!(matches!(
+            read.read_record(&mut return_reader(input)),
+            Err(Fastq2CompError::MalformedRecord {record: 1, offset: 0, ..})
+        ));
+
+        // too few fields, invalid flag and length mismatch are all skipped
+        let mut read = SAMRead::sam(5);
+        read.mode = ParseMode::Lenient;
+        assert_eq!(read.read_record(&mut return_reader(input)).unwrap(), None);
+        assert_eq!(read.records_skipped(), 3);
+    }
+
+    #[test]
+    fn test_read_bam() {
+        let input = bam(&[
+            bam_record(b"r1", 4, b"ACGTN", Some(b"IIII#")),
+            bam_record(b"r2", 20, b"AACG", Some(b"ABCD")),
+            bam_record(b"r3", 256, b"GGGGG", Some(b"IIIII")),
+            bam_record(b"r4", 4, b"TTT", None),
+        ]);
+        let mut read = SAMRead::bam(5);
+        let mut reader = return_reader(&input);
+        let mut reads = Vec::new();
+        while read.read_record(&mut reader).unwrap().is_some() {
+            reads.push((read.seq().to_vec(), read.quals().map(<[u8]>::to_vec), read.record()));
+        }
+
+        assert_eq!(reads, vec![
+            (b"ACGTN".to_vec(), Some(b"IIII#".to_vec()), 1),
+            (b"CGTT".to_vec(), Some(b"DCBA".to_vec()), 2),
+            (b"TTT".to_vec(), None, 4),
+        ]);
+    }
+
+    #[test]
+    fn test_malformed_bam() {
+        let mut read = SAMRead::bam(5);
+        assert!(matches!(
+            read.read_record(&mut return_reader(b"BAN\x01")),
+            Err(Fastq2CompError::MalformedRecord {record: 1, offset: 0, ..})
+        ));
+
+        // truncated record
+        let input = bam(&[bam_record(b"r1", 4, b"ACGTN", None)]);
+        let mut read = SAMRead::bam(5);
+        assert!(matches!(
+            read.read_record(&mut return_reader(&input[..input.len() - 2])),
+            Err(Fastq2CompError::MalformedRecord {record: 1, ..})
+        ));
+
+        // sequence longer than record, skipped in lenient mode
+        let mut bad = bam_record(b"r1", 4, b"ACGTN", None);
+        bad[20] = 50;
+        let input = bam(&[bad, bam_record(b"r2", 4, b"ACG", None)]);
+        let mut read = SAMRead::bam(5);
+        read.mode = ParseMode::Lenient;
+        let mut reader = return_reader(&input);
+        assert_eq!(read.read_record(&mut reader).unwrap(), Some(()));
+        assert_eq!((read.seq(), read.record(), read.records_skipped()), (&b"ACG"[..], 2, 1));
+    }
+
+    #[test]
+    fn test_oversized_bam() {
+        // a block size beyond the limit is rejected without reading the record into memory
+        let mut input = bam(&[]);
+        input.extend_from_slice(&u32::MAX.to_le_bytes());
+        input.extend_from_slice(&[0; 64]);
+        let mut read = SAMRead::bam(5);
+        let res = read.read_record(&mut return_reader(&input));
+        assert!(matches!(res, Err(Fastq2CompError::MalformedRecord {record: 1, ref reason, ..}) if reason.contains("limit")), "{:?}", res);
+
+        // as is one within it but running past the end of input
+        let mut input = bam(&[]);
+        input.extend_from_slice(&((MAX_BAM_RECORD - 1) as u32).to_le_bytes());
+        input.extend_from_slice(&[0; 64]);
+        let mut read = SAMRead::bam(5);
+        let res = read.read_record(&mut return_reader(&input));
+        assert!(matches!(res, Err(Fastq2CompError::MalformedRecord {record: 1, ref reason, ..}) if reason.contains("end of input")), "{:?}", res);
+        assert!(read.buf.capacity() < 1 << 20);
+    }
+
+    #[test]
+    fn test_complement() {
+        assert_eq!(b"ACGTRYKMBVDHSWN=acgtrykmbvdhswn".iter().map(|&b| complement(b)).collect::<Vec<_>>(), b"TGCAYRMKVBHDSWN=tgcayrmkvbhdswn".to_vec());
+
+        // ambiguity codes of reverse strand reads are complemented along with the bases
+        let input = bam(&[bam_record(b"r1", 20, b"ARKBDSN", None)]);
+        let mut read = SAMRead::bam(7);
+        read.read_record(&mut return_reader(&input)).unwrap();
+        assert_eq!(read.seq(), b"NSHVMYT");
+    }
+
+    #[test]
+    fn test_paired_sam() {
+        // mates are an error even in lenient mode, secondary records of them are skipped first
+        let input = bam(&[
+            bam_record(b"r1", 4 | 0x100, b"ACGT", None),
+            bam_record(b"r1", 77, b"ACGT", None),
+            bam_record(b"r1", 141, b"TTGA", None),
+        ]);
+        let mut read = SAMRead::bam(4);
+        read.mode = ParseMode::Lenient;
+        assert!(matches!(read.read_record(&mut return_reader(&input)), Err(Fastq2CompError::PairedSam {record: 2})));
+
+        let mut read = SAMRead::sam(4);
+        let res = read.read_record(&mut return_reader(b"r1\t141\t*\t0\t0\t*\t*\t0\t0\tTTGA\tIIII\n"));
+        assert!(matches!(res, Err(Fastq2CompError::PairedSam {record: 1})));
+    }
+
+    #[test]
+    fn test_sam_run() {
+        let args = SampleArgs {target_read_count: None, min_phred_score: 30, trimmed_length: 4, ..SampleArgs::default()};
+
+        // r2 fails the quality check, r5 has no qualities so is not filtered
+        let comp = crate::extract_comp::run(FASTQReader::sam(args, return_reader(SAM))).unwrap();
+        assert_eq!((comp.reads_read(), comp.reads_accepted()), (2, 2));
+        assert_eq!(comp.lib[0].bases.A, 1);
+        assert_eq!(comp.lib[0].bases.T, 1);
+    }
+}
+
+/// Characters encoded by the 4-bit codes of BAM sequences
+const BAM_BASES: &[u8; 16] = b"=ACMGRSVTWYHKDBN";
+
+/// Largest BAM record accepted, in bytes, well above that of any real read (even ultra-long reads with tags)
+const MAX_BAM_RECORD: u64 = 1 << 28;
+
+/// FLAG bits of SAM/BAM records
+const FLAG_REVERSE: u16 = 0x10;
+const FLAG_READ1: u16 = 0x40;
+const FLAG_READ2: u16 = 0x80;
+const FLAG_SECONDARY: u16 = 0x100;
+const FLAG_SUPPLEMENTARY: u16 = 0x800;
+
+/// Abstraction for a single record of (unaligned) SAM or BAM data.
+/// Secondary and supplementary records are skipped, and reads on the reverse strand are reverse complemented,
+/// so each read is returned once, in its original orientation.
+/// Records of paired-end reads (read 1 or read 2 flag set) are an error, as mates would be counted together.
+///
+/// BAM input must be decompressed already, e.g. by [`crate::io_utils::decompressed_reader`].
+#[derive(Debug)]
+pub struct SAMRead {
+    seq: Vec<u8>,
+    quals: Vec<u8>,
+    /// Set if the record currently held has qualities, which are optional in SAM/BAM
+    has_quals: bool,
+    /// Last line read (SAM), or last record read (BAM)
+    buf: Vec<u8>,
+    bam: bool,
+    /// Set once the header of BAM input has been read
+    header_read: bool,
+    pub(crate) mode: ParseMode,
+    /// 1-based index of the record currently held (including skipped secondary and supplementary records),
+    /// 0 if nothing has been read yet.
+    record: u64,
+    /// Number of malformed records skipped in lenient mode
+    records_skipped: u64,
+    /// Byte offset into input of the record currently held
+    record_offset: u64,
+    /// Bytes consumed from input so far
+    offset: u64,
+}
+
+impl SAMRead {
+    fn new (len: usize, bam: bool) -> SAMRead {
+        SAMRead {
+            seq: Vec::with_capacity(len),
+            quals: Vec::with_capacity(len),
+            has_quals: false,
+            buf: Vec::new(),
+            bam,
+            header_read: false,
+            mode: ParseMode::Strict,
+            record: 0,
+            records_skipped: 0,
+            record_offset: 0,
+            offset: 0,
+        }
+    }
+
+    pub(crate) fn sam (len: usize) -> SAMRead {
+        SAMRead::new(len, false)
+    }
+
+    pub(crate) fn bam (len: usize) -> SAMRead {
+        SAMRead::new(len, true)
+    }
+
+    /// Reads records until one which is neither secondary nor supplementary.
+    /// In lenient mode, malformed records are skipped and counted.
+    fn read_primary (&mut self, reader: &mut impl BufRead) -> Result<Option<()>, Fastq2CompError> {
+        loop {
+            let res = if self.bam {self.read_bam(reader)} else {self.read_sam(reader)};
+            match res {
+                Ok(Some(flag)) if flag & (FLAG_SECONDARY | FLAG_SUPPLEMENTARY) != 0 => {},
+                Ok(Some(flag)) if flag & (FLAG_READ1 | FLAG_READ2) != 0 => {
+                    return Err(Fastq2CompError::PairedSam {record: self.record});
+                },
+                Ok(Some(flag)) => {
+                    if flag & FLAG_REVERSE != 0 {
+                        self.seq.reverse();
+                        self.seq.iter_mut().for_each(|base| *base = complement(*base));
+                        self.quals.reverse();
+                    }
+                    return Ok(Some(()));
+                },
+                Ok(None) => return Ok(None),
+                Err(Fastq2CompError::MalformedRecord {..}) if self.mode == ParseMode::Lenient && self.header_read => {
+                    self.records_skipped += 1;
+                },
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Reads the next alignment line of SAM input, skipping header lines.
+    /// Returns the FLAG of the record.
+    fn read_sam (&mut self, reader: &mut impl BufRead) -> Result<Option<u16>, Fastq2CompError> {
+        // SAM has no header to check, any problems are with individual records
+        self.header_read = true;
+
+        loop {
+            self.record_offset = self.offset;
+            self.buf.clear();
+            let n = reader.read_until(b'\n', &mut self.buf)?;
+            self.offset += n as u64;
+            if n == 0 {
+                return Ok(None);
+            }
+
+            if self.buf.ends_with(b"\n") {
+                self.buf.pop();
+                if self.buf.ends_with(b"\r") {
+                    self.buf.pop();
+                }
+            }
+            if !self.buf.is_empty() && !self.buf.starts_with(b"@") {break}
+        }
+        self.record += 1;
+
+        let fields: Vec<&[u8]> = self.buf.split(|&c| c == b'\t').take(11).collect();
+        if fields.len() < 11 {
+            return Err(self.malformed(format!("expected at least 11 fields, found {}", fields.len())));
+        }
+        let flag = match std::str::from_utf8(fields[1]).ok().and_then(|f| f.parse::<u16>().ok()) {
+            Some(flag) => flag,
+            None => return Err(self.malformed(format!("invalid FLAG {:?}", String::from_utf8_lossy(fields[1])))),
+        };
+        let (seq, quals) = (fields[9], fields[10]);
+
+        self.seq.clear();
+        self.quals.clear();
+        if seq != b"*" {
+            self.seq.extend_from_slice(seq);
+        }
+        self.has_quals = quals != b"*";
+        if self.has_quals {
+            if quals.len() != seq.len() {
+                return Err(self.malformed(format!(
+                    "sequence length ({}) does not match quality length ({})", seq.len(), quals.len()
+                )));
+            }
+            self.quals.extend_from_slice(quals);
+        }
+
+        Ok(Some(flag))
+    }
+
+    /// Reads the next alignment record of BAM input, reading the header first if needed.
+    /// Returns the FLAG of the record.
+    fn read_bam (&mut self, reader: &mut impl BufRead) -> Result<Option<u16>, Fastq2CompError> {
+        if !self.header_read {
+            self.read_bam_header(reader)?;
+            self.header_read = true;
+        }
+
+        if reader.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+        self.record_offset = self.offset;
+        self.record += 1;
+
+        // Read through `take`, so the buffer only grows as far as input actually goes
+        let block_size = self.read_u32(reader)? as u64;
+        if block_size > MAX_BAM_RECORD {
+            // Skipped as far as input goes, so lenient parsing carries on after the record
+            self.offset += io::copy(&mut reader.take(block_size), &mut io::sink())?;
+            return Err(self.malformed(format!("record of {} bytes exceeds the limit of {} bytes", block_size, MAX_BAM_RECORD)));
+        }
+        self.buf.clear();
+        let n = reader.take(block_size).read_to_end(&mut self.buf)? as u64;
+        self.offset += n;
+        if n != block_size {
+            return Err(self.malformed("unexpected end of input".to_string()));
+        }
+
+        let buf = &self.buf;
+        let field = |offset: usize, len: usize| buf.get(offset..offset + len);
+        let fixed = match field(0, 32) {
+            Some(fixed) => fixed,
+            None => return Err(self.malformed(format!("record of {} bytes is too short", block_size))),
+        };
+        let u16_at = |offset: usize| u16::from_le_bytes([fixed[offset], fixed[offset + 1]]) as usize;
+        let (l_read_name, n_cigar_op, flag) = (fixed[8] as usize, u16_at(12), u16_at(14) as u16);
+        let l_seq = u32::from_le_bytes([fixed[16], fixed[17], fixed[18], fixed[19]]) as usize;
+
+        let seq_offset = 32 + l_read_name + 4 * n_cigar_op;
+        let seq_len = l_seq.div_ceil(2);
+        let (seq, quals) = match (field(seq_offset, seq_len), field(seq_offset + seq_len, l_seq)) {
+            (Some(seq), Some(quals)) => (seq, quals),
+            _ => return Err(self.malformed(format!("sequence of length {} does not fit in record", l_seq))),
+        };
+
+        self.seq.clear();
+        self.seq.extend(
+            seq.iter().flat_map(|&b| [BAM_BASES[(b >> 4) as usize], BAM_BASES[(b & 0xf) as usize]]).take(l_seq)
+        );
+        // Missing qualities are stored as 0xff, present ones are converted to FASTQ style Phred+33
+        self.has_quals = quals.first().is_none_or(|&q| q != 0xff);
+        self.quals.clear();
+        if self.has_quals {
+            self.quals.extend(quals.iter().map(|q| q.saturating_add(33)));
+        }
+
+        Ok(Some(flag))
+    }
+
+    /// Reads and checks the header of BAM input: magic, SAM header text and reference sequences.
+    fn read_bam_header (&mut self, reader: &mut impl BufRead) -> Result<(), Fastq2CompError> {
+        let mut magic = [0; 4];
+        self.read_exact(reader, &mut magic)?;
+        if &magic != b"BAM\x01" {
+            return Err(self.malformed("input does not start with BAM magic".to_string()));
+        }
+
+        let l_text = self.read_u32(reader)?;
+        self.skip(reader, l_text as u64)?;
+        for _ in 0..self.read_u32(reader)? {
+            let l_name = self.read_u32(reader)?;
+            // name, and length of reference sequence
+            self.skip(reader, l_name as u64 + 4)?;
+        }
+        Ok(())
+    }
+
+    fn read_u32 (&mut self, reader: &mut impl BufRead) -> Result<u32, Fastq2CompError> {
+        let mut bytes = [0; 4];
+        self.read_exact(reader, &mut bytes)?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn skip (&mut self, reader: &mut impl BufRead, len: u64) -> Result<(), Fastq2CompError> {
+        let skipped = io::copy(&mut reader.take(len), &mut io::sink())?;
+        self.offset += skipped;
+        if skipped != len {
+            return Err(self.malformed("unexpected end of input".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Fills `buf` from `reader`, reporting a truncated record if input ends first.
+    fn read_exact (&mut self, reader: &mut impl BufRead, buf: &mut [u8]) -> Result<(), Fastq2CompError> {
+        match reader.read_exact(buf) {
+            Ok(()) => {
+                self.offset += buf.len() as u64;
+                Ok(())
+            },
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Err(self.malformed("unexpected end of input".to_string())),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn malformed (&self, reason: String) -> Fastq2CompError {
+        Fastq2CompError::MalformedRecord {record: self.record.max(1), offset: self.record_offset, reason}
+    }
+}
+
+/// Complement of a base or IUPAC ambiguity code, keeping its case.
+/// Self-complementary codes (`S`, `W` and `N`) and any other characters are returned unchanged.
+fn complement (base: u8) -> u8 {
+    let complement = match base.to_ascii_uppercase() {
+        b'A' => b'T',
+        b'T' => b'A',
+        b'G' => b'C',
+        b'C' => b'G',
+        b'R' => b'Y',
+        b'Y' => b'R',
+        b'K' => b'M',
+        b'M' => b'K',
+        b'B' => b'V',
+        b'V' => b'B',
+        b'D' => b'H',
+        b'H' => b'D',
+        _ => return base,
+    };
+    if base.is_ascii_lowercase() {complement.to_ascii_lowercase()} else {complement}
+}
+
+impl RecordSource for SAMRead {
+    fn read_record<R: BufRead> (&mut self, reader: &mut R) -> Result<Option<()>, Fastq2CompError> {
+        self.read_primary(reader)
+    }
+
+    fn seq (&self) -> &[u8] {
+        &self.seq
+    }
+
+    fn quals (&self) -> Option<&[u8]> {
+        if self.has_quals {Some(&self.quals)} else {None}
+    }
+
+    fn quality_encoding (&self) -> Option<QualityEncoding> {
+        // Fixed by the SAM specification, and converted to it for BAM
+        Some(QualityEncoding::Sanger)
+    }
+
+    fn record (&self) -> u64 {
+        self.record
+    }
+
+    fn records_skipped (&self) -> u64 {
+        self.records_skipped
+    }
+}
+
+impl<T: BufRead> FASTQReader<T, SAMRead> {
+    /// Reads unaligned SAM input instead of FASTQ
+    pub fn sam (args: SampleArgs, reader: T) -> FASTQReader<T, SAMRead> {
+        let mut read = SAMRead::sam(args.trimmed_length);
+        read.mode = args.parse_mode;
+
+        FASTQReader::with_source(args, read, reader)
+    }
+
+    /// Reads unaligned BAM input instead of FASTQ. `reader` must already be decompressed from BGZF.
+    pub fn bam (args: SampleArgs, reader: T) -> FASTQReader<T, SAMRead> {
+        let mut read = SAMRead::bam(args.trimmed_length);
+        read.mode = args.parse_mode;
+
+        FASTQReader::with_source(args, read, reader)
+    }
+}